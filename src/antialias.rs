@@ -0,0 +1,503 @@
+//! Anti-aliased lines, circles, arcs and rounded rectangles for the 4 gray panels
+//!
+//! The coverage of every pixel by the stroke is computed from its distance to the outline and
+//! quantized onto the gray levels between the stroke color and the background, so edges are
+//! drawn with `Gray1`/`Gray2` instead of jagged steps.
+//!
+//! ```rust,ignore
+//! let style = AaStyle::new(TwoBitColor::Black, 2);
+//! AaCircle::new(Point::new(48, 48), 40).into_styled(style).draw(&mut display)?;
+//! AaLine::new(Point::new(48, 48), needle_tip).into_styled(style).draw(&mut display)?;
+//! ```
+
+use embedded_graphics_core::prelude::*;
+use embedded_graphics_core::primitives::Rectangle;
+use embedded_graphics::geometry::Angle;
+
+use crate::color::TwoBitColor;
+
+/// Stroke of the anti-aliased primitives
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AaStyle {
+    /// Color of the stroke
+    pub color: TwoBitColor,
+    /// Color the stroke is blended with at its edges, pixels which would get this color
+    /// are left untouched
+    pub background: TwoBitColor,
+    /// Width of the stroke, centered on the outline
+    pub stroke_width: u32,
+}
+
+impl AaStyle {
+    /// Stroke of `color` on a white background
+    pub const fn new(color: TwoBitColor, stroke_width: u32) -> Self {
+        AaStyle {
+            color,
+            background: TwoBitColor::White,
+            stroke_width,
+        }
+    }
+
+    /// Changes the background the edges are blended with
+    pub const fn with_background(mut self, background: TwoBitColor) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Color of a pixel covered by `coverage` (0.0-1.0), `None` if it stays background
+    fn blend(&self, coverage: f32) -> Option<TwoBitColor> {
+        let background = f32::from(self.background.level());
+        let color = f32::from(self.color.level());
+        let level = (background + (color - background) * coverage + 0.5) as u8;
+        (level != self.background.level()).then(|| TwoBitColor::from_level(level))
+    }
+}
+
+/// Shapes which are drawn by their distance to the outline
+pub trait AaPrimitive: Sized {
+    /// Distance field the pixels are shaded from
+    type Field: DistanceField;
+
+    /// Smallest rectangle which contains the outline, without the stroke
+    fn outline_box(&self) -> Rectangle;
+
+    /// Prepares the distance field, once per draw
+    fn field(&self) -> Self::Field;
+
+    /// Combines the shape with a stroke
+    fn into_styled(self, style: AaStyle) -> AaStyled<Self> {
+        AaStyled {
+            primitive: self,
+            style,
+        }
+    }
+}
+
+/// Distance of pixel centers to an outline
+pub trait DistanceField {
+    /// Distance of the pixel center `(x, y)` to the outline
+    fn distance(&self, x: f32, y: f32) -> f32;
+
+    /// Range of x on row `y` which can be closer than `reach` to the outline, `None` if the
+    /// row is too far away
+    ///
+    /// The default is the whole row, so the draw scans the box around the outline.
+    fn row_span(&self, _y: f32, _reach: f32) -> Option<(f32, f32)> {
+        Some((f32::NEG_INFINITY, f32::INFINITY))
+    }
+}
+
+/// Anti-aliased shape with its stroke, draws on any `TwoBitColor` target
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AaStyled<P> {
+    /// The shape
+    pub primitive: P,
+    /// The stroke
+    pub style: AaStyle,
+}
+
+impl<P: AaPrimitive> Drawable for AaStyled<P> {
+    type Color = TwoBitColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = TwoBitColor>,
+    {
+        let half = self.style.stroke_width as f32 / 2.0;
+        let margin = self.style.stroke_width / 2 + 1;
+        let outline = self.primitive.outline_box();
+        let area = Rectangle::new(
+            outline.top_left - Point::new(margin as i32, margin as i32),
+            outline.size + Size::new(2 * margin, 2 * margin),
+        )
+        .intersection(&target.bounding_box());
+
+        // pixels further away than this are not covered at all
+        let reach = half + 0.5;
+        let field = self.primitive.field();
+        let field = &field;
+        let pixels = area.rows().flat_map(|y| {
+            let columns = field.row_span(y as f32, reach).map_or(0..0, |(left, right)| {
+                let start = (left as i32).saturating_sub(1).max(area.top_left.x);
+                let end = (right as i32).saturating_add(2).min(area.columns().end);
+                start..end
+            });
+            columns.filter_map(move |x| {
+                let distance = field.distance(x as f32, y as f32);
+                let color = self.style.blend((reach - distance).clamp(0.0, 1.0))?;
+                Some(Pixel(Point::new(x, y), color))
+            })
+        });
+        target.draw_iter(pixels)
+    }
+}
+
+/// Line from `start` to `end` with round caps
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AaLine {
+    /// Start point
+    pub start: Point,
+    /// End point
+    pub end: Point,
+}
+
+impl AaLine {
+    /// Creates a line between two points
+    pub const fn new(start: Point, end: Point) -> Self {
+        AaLine { start, end }
+    }
+}
+
+impl AaPrimitive for AaLine {
+    type Field = Self;
+
+    fn outline_box(&self) -> Rectangle {
+        Rectangle::with_corners(self.start, self.end)
+    }
+
+    fn field(&self) -> Self {
+        *self
+    }
+}
+
+impl DistanceField for AaLine {
+    fn distance(&self, x: f32, y: f32) -> f32 {
+        segment_distance(
+            (self.start.x as f32, self.start.y as f32),
+            (self.end.x as f32, self.end.y as f32),
+            (x, y),
+        )
+    }
+
+    /// Only the stroke band around the segment, a few pixels per row
+    fn row_span(&self, y: f32, reach: f32) -> Option<(f32, f32)> {
+        let (x0, y0) = (self.start.x as f32, self.start.y as f32);
+        let (x1, y1) = (self.end.x as f32, self.end.y as f32);
+        // part of the segment within reach of the row, every covered pixel is within reach
+        // of a point on it
+        let (t0, t1) = if y0 == y1 {
+            if (y - y0).abs() > reach {
+                return None;
+            }
+            (0.0, 1.0)
+        } else {
+            let a = (y - reach - y0) / (y1 - y0);
+            let b = (y + reach - y0) / (y1 - y0);
+            (a.min(b).max(0.0), a.max(b).min(1.0))
+        };
+        if t0 > t1 {
+            return None;
+        }
+        let (a, b) = (x0 + (x1 - x0) * t0, x0 + (x1 - x0) * t1);
+        Some((a.min(b) - reach, a.max(b) + reach))
+    }
+}
+
+/// Circle around `center`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AaCircle {
+    /// Center point
+    pub center: Point,
+    /// Radius to the middle of the stroke
+    pub radius: u32,
+}
+
+impl AaCircle {
+    /// Creates a circle from its center and radius
+    pub const fn new(center: Point, radius: u32) -> Self {
+        AaCircle { center, radius }
+    }
+}
+
+impl AaPrimitive for AaCircle {
+    type Field = Self;
+
+    fn outline_box(&self) -> Rectangle {
+        let radius = self.radius as i32;
+        Rectangle::with_corners(
+            self.center - Point::new(radius, radius),
+            self.center + Point::new(radius, radius),
+        )
+    }
+
+    fn field(&self) -> Self {
+        *self
+    }
+}
+
+impl DistanceField for AaCircle {
+    fn distance(&self, x: f32, y: f32) -> f32 {
+        let (dx, dy) = (x - self.center.x as f32, y - self.center.y as f32);
+        (sqrt(dx * dx + dy * dy) - self.radius as f32).abs()
+    }
+}
+
+/// Part of a circle with round caps
+///
+/// Angles are measured like the arcs of embedded-graphics: 0° points right and positive
+/// angles turn clockwise.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AaArc {
+    /// Center point
+    pub center: Point,
+    /// Radius to the middle of the stroke
+    pub radius: u32,
+    /// Angle of the start
+    pub angle_start: Angle,
+    /// Length of the arc, negative sweeps turn counterclockwise
+    pub angle_sweep: Angle,
+}
+
+impl AaArc {
+    /// Creates an arc from its center, radius, start angle and sweep
+    pub const fn new(center: Point, radius: u32, angle_start: Angle, angle_sweep: Angle) -> Self {
+        AaArc {
+            center,
+            radius,
+            angle_start,
+            angle_sweep,
+        }
+    }
+}
+
+impl AaPrimitive for AaArc {
+    type Field = ArcField;
+
+    fn outline_box(&self) -> Rectangle {
+        AaCircle::new(self.center, self.radius).outline_box()
+    }
+
+    fn field(&self) -> ArcField {
+        let start = self.angle_start.to_radians();
+        let sweep = self.angle_sweep.to_radians();
+        let (from, sweep) = if sweep < 0.0 { (start + sweep, -sweep) } else { (start, sweep) };
+        ArcField {
+            circle: AaCircle::new(self.center, self.radius),
+            start: sin_cos(from),
+            end: sin_cos(from + sweep),
+            sweep,
+        }
+    }
+}
+
+/// Distance field of an [AaArc]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ArcField {
+    circle: AaCircle,
+    /// Sine and cosine of the start direction, the ends are ordered clockwise
+    start: (f32, f32),
+    /// Sine and cosine of the end direction
+    end: (f32, f32),
+    /// Clockwise sweep from start to end in radians
+    sweep: f32,
+}
+
+impl DistanceField for ArcField {
+    fn distance(&self, x: f32, y: f32) -> f32 {
+        let center = self.circle.center;
+        let (dx, dy) = (x - center.x as f32, y - center.y as f32);
+        let ((sin_a, cos_a), (sin_b, cos_b), sweep) = (self.start, self.end, self.sweep);
+        if sweep >= core::f32::consts::TAU {
+            return self.circle.distance(x, y);
+        }
+
+        // clockwise of the start and counterclockwise of the end direction
+        let after_start = cos_a * dy - sin_a * dx >= 0.0;
+        let before_end = cos_b * dy - sin_b * dx <= 0.0;
+        let inside = if sweep <= core::f32::consts::PI {
+            after_start && before_end
+        } else {
+            after_start || before_end
+        };
+        if inside {
+            return self.circle.distance(x, y);
+        }
+
+        let radius = self.circle.radius as f32;
+        let cap = |sin: f32, cos: f32| {
+            let (ex, ey) = (dx - cos * radius, dy - sin * radius);
+            sqrt(ex * ex + ey * ey)
+        };
+        cap(sin_a, cos_a).min(cap(sin_b, cos_b))
+    }
+}
+
+/// Rectangle with rounded corners, the outline runs through the outermost pixels of `rectangle`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AaRoundedRectangle {
+    /// The rectangle
+    pub rectangle: Rectangle,
+    /// Radius of the corners, limited to half of the shorter side
+    pub corner_radius: u32,
+}
+
+impl AaRoundedRectangle {
+    /// Creates a rounded rectangle with the same radius at all corners
+    pub const fn new(rectangle: Rectangle, corner_radius: u32) -> Self {
+        AaRoundedRectangle {
+            rectangle,
+            corner_radius,
+        }
+    }
+}
+
+impl AaPrimitive for AaRoundedRectangle {
+    type Field = Self;
+
+    fn outline_box(&self) -> Rectangle {
+        self.rectangle
+    }
+
+    fn field(&self) -> Self {
+        *self
+    }
+}
+
+impl DistanceField for AaRoundedRectangle {
+    fn distance(&self, x: f32, y: f32) -> f32 {
+        let Some(bottom_right) = self.rectangle.bottom_right() else {
+            return f32::MAX;
+        };
+        let top_left = self.rectangle.top_left;
+        let half_w = (bottom_right.x - top_left.x) as f32 / 2.0;
+        let half_h = (bottom_right.y - top_left.y) as f32 / 2.0;
+        let radius = (self.corner_radius as f32).min(half_w).min(half_h);
+
+        // distance from the rectangle shrunk by the radius
+        let qx = (x - top_left.x as f32 - half_w).abs() - half_w + radius;
+        let qy = (y - top_left.y as f32 - half_h).abs() - half_h + radius;
+        let (ox, oy) = (qx.max(0.0), qy.max(0.0));
+        let signed = sqrt(ox * ox + oy * oy) + qx.max(qy).min(0.0) - radius;
+        signed.abs()
+    }
+}
+
+/// Distance of `p` to the segment from `a` to `b`
+fn segment_distance(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    let (bx, by) = (b.0 - a.0, b.1 - a.1);
+    let (px, py) = (p.0 - a.0, p.1 - a.1);
+    let length = bx * bx + by * by;
+    let t = if length > 0.0 {
+        ((px * bx + py * by) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (dx, dy) = (px - bx * t, py - by * t);
+    sqrt(dx * dx + dy * dy)
+}
+
+/// Square root without libm, accurate to a few ulp which is plenty for coverage
+fn sqrt(value: f32) -> f32 {
+    if value <= 0.0 {
+        return 0.0;
+    }
+    let mut root = f32::from_bits((value.to_bits() >> 1) + 0x1fbd_1df5);
+    for _ in 0..3 {
+        root = 0.5 * (root + value / root);
+    }
+    root
+}
+
+/// Sine and cosine of `angle` in radians
+fn sin_cos(angle: f32) -> (f32, f32) {
+    (sin(angle), sin(angle + core::f32::consts::FRAC_PI_2))
+}
+
+fn sin(angle: f32) -> f32 {
+    use core::f32::consts::{PI, TAU};
+    // reduce to -π..π and then to -π/2..π/2, where the series converges fast
+    let mut x = angle - TAU * ((angle + PI) / TAU) as i32 as f32;
+    if x < -PI {
+        x += TAU;
+    }
+    if x > PI / 2.0 {
+        x = PI - x;
+    } else if x < -PI / 2.0 {
+        x = -PI - x;
+    }
+    let x2 = x * x;
+    x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0))))
+}
+
+#[cfg(all(test, feature = "uc1638"))]
+mod tests {
+    use super::*;
+    use crate::uc1638::prelude::Display2in7;
+    use embedded_graphics::image::GetPixel;
+    use embedded_graphics::primitives::PointsIter;
+
+    #[test]
+    fn math_helpers() {
+        assert!((sqrt(2.0) - core::f32::consts::SQRT_2).abs() < 1e-5);
+        for degrees in [-270.0, -90.0, 0.0, 30.0, 135.0, 200.0, 400.0] {
+            let (sin, cos) = sin_cos(Angle::from_degrees(degrees).to_radians());
+            assert!((sin * sin + cos * cos - 1.0).abs() < 1e-3, "{}", degrees);
+        }
+        assert!((sin_cos(Angle::from_degrees(30.0).to_radians()).0 - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn line_edges_are_gray() {
+        let mut display = Display2in7::default();
+        let style = AaStyle::new(TwoBitColor::Black, 1);
+        let _ = AaLine::new(Point::new(0, 0), Point::new(10, 5))
+            .into_styled(style)
+            .draw(&mut display);
+        assert_eq!(display.pixel(Point::new(0, 0)), Some(TwoBitColor::Black));
+        assert_eq!(display.pixel(Point::new(10, 5)), Some(TwoBitColor::Black));
+        // the line passes halfway between (1, 0) and (1, 1) and right through (2, 1)
+        assert_eq!(display.pixel(Point::new(1, 0)), Some(TwoBitColor::Gray2));
+        assert_eq!(display.pixel(Point::new(1, 1)), Some(TwoBitColor::Gray2));
+        assert_eq!(display.pixel(Point::new(2, 1)), Some(TwoBitColor::Black));
+        assert_eq!(display.pixel(Point::new(0, 4)), Some(TwoBitColor::White));
+    }
+
+    #[test]
+    fn line_rows_only_scan_the_stroke() {
+        for (end, width) in [((30, 7), 1), ((3, 40), 3), ((-20, -20), 2), ((25, 0), 4)] {
+            let line = AaLine::new(Point::new(5, 5), Point::new(end.0, end.1));
+            let reach = width as f32 / 2.0 + 0.5;
+            let area = line.outline_box().offset(width + 2);
+            for point in area.points() {
+                let (x, y) = (point.x as f32, point.y as f32);
+                if line.distance(x, y) < reach {
+                    let (left, right) = line.row_span(y, reach).unwrap();
+                    assert!(left <= x && x <= right, "{:?} {:?}", line, point);
+                }
+            }
+        }
+        // a shallow line scans a band instead of the whole box
+        let line = AaLine::new(Point::new(0, 0), Point::new(100, 10));
+        let (left, right) = line.row_span(5.0, 1.0).unwrap();
+        assert!(right - left < 25.0);
+        assert_eq!(line.row_span(20.0, 1.0), None);
+    }
+
+    #[test]
+    fn arc_only_covers_its_sweep() {
+        let mut display = Display2in7::default();
+        let style = AaStyle::new(TwoBitColor::Black, 1);
+        let arc = AaArc::new(
+            Point::new(20, 20),
+            10,
+            Angle::from_degrees(0.0),
+            Angle::from_degrees(90.0),
+        );
+        let _ = arc.into_styled(style).draw(&mut display);
+        // 0° is right, 90° is down
+        assert_eq!(display.pixel(Point::new(30, 20)), Some(TwoBitColor::Black));
+        assert_eq!(display.pixel(Point::new(20, 30)), Some(TwoBitColor::Black));
+        assert_eq!(display.pixel(Point::new(10, 20)), Some(TwoBitColor::White));
+        assert_eq!(display.pixel(Point::new(20, 10)), Some(TwoBitColor::White));
+
+        let rounded = AaRoundedRectangle::new(
+            Rectangle::new(Point::new(40, 10), Size::new(21, 11)),
+            4,
+        );
+        let _ = rounded.into_styled(style).draw(&mut display);
+        assert_eq!(display.pixel(Point::new(50, 10)), Some(TwoBitColor::Black));
+        assert_eq!(display.pixel(Point::new(40, 10)), Some(TwoBitColor::White));
+        assert_eq!(display.pixel(Point::new(50, 15)), Some(TwoBitColor::White));
+    }
+}
@@ -1,8 +1,11 @@
 //! B/W Color for EPDs
 
-use embedded_graphics::pixelcolor::raw::RawU2;
+use embedded_graphics::pixelcolor::raw::{RawU2, RawU4};
 use embedded_graphics::pixelcolor::BinaryColor;
 use embedded_graphics::pixelcolor::PixelColor;
+use embedded_graphics::pixelcolor::{Gray2, Gray4, Gray8, GrayColor, Rgb888};
+
+use crate::palette::{Distance, Palette};
 
 
 pub use BinaryColor::Off as White;
@@ -91,35 +94,7 @@ impl From<OctColor> for embedded_graphics_core::pixelcolor::Rgb888 {
 
 impl From<embedded_graphics_core::pixelcolor::Rgb888> for OctColor {
     fn from(p: embedded_graphics_core::pixelcolor::Rgb888) -> OctColor {
-        use embedded_graphics_core::prelude::RgbColor;
-        let colors = [
-            OctColor::Black,
-            OctColor::White,
-            OctColor::Green,
-            OctColor::Blue,
-            OctColor::Red,
-            OctColor::Yellow,
-            OctColor::Orange,
-            OctColor::HiZ,
-        ];
-        // if the user has already mapped to the right color space, it will just be in the list
-        if let Some(found) = colors.iter().find(|c| c.rgb() == (p.r(), p.g(), p.b())) {
-            return *found;
-        }
-
-        // This is not ideal but just pick the nearest color
-        *colors
-            .iter()
-            .map(|c| (c, c.rgb()))
-            .map(|(c, (r, g, b))| {
-                let dist = (i32::from(r) - i32::from(p.r())).pow(2)
-                    + (i32::from(g) - i32::from(p.g())).pow(2)
-                    + (i32::from(b) - i32::from(p.b())).pow(2);
-                (c, dist)
-            })
-            .min_by_key(|(_c, dist)| *dist)
-            .map(|(c, _)| c)
-            .unwrap_or(&OctColor::White)
+        OctColor::PALETTE.nearest(p)
     }
 }
 
@@ -137,6 +112,18 @@ impl PixelColor for OctColor {
 }
 
 impl OctColor {
+    /// All colors with their rgb values, used to convert `Rgb888`
+    pub const PALETTE: Palette<OctColor, 8> = Palette::new([
+        (OctColor::Black, Rgb888::new(0x00, 0x00, 0x00)),
+        (OctColor::White, Rgb888::new(0xff, 0xff, 0xff)),
+        (OctColor::Green, Rgb888::new(0x00, 0xff, 0x00)),
+        (OctColor::Blue, Rgb888::new(0x00, 0x00, 0xff)),
+        (OctColor::Red, Rgb888::new(0xff, 0x00, 0x00)),
+        (OctColor::Yellow, Rgb888::new(0xff, 0xff, 0x00)),
+        (OctColor::Orange, Rgb888::new(0xff, 0x80, 0x00)),
+        (OctColor::HiZ, Rgb888::new(0x80, 0x80, 0x80)),
+    ]);
+
     /// Gets the Nibble representation of the Color as needed by the display
     pub fn get_nibble(self) -> u8 {
         self as u8
@@ -168,16 +155,9 @@ impl OctColor {
     }
     /// Converts to limited range of RGB values.
     pub fn rgb(self) -> (u8, u8, u8) {
-        match self {
-            OctColor::White => (0xff, 0xff, 0xff),
-            OctColor::Black => (0x00, 0x00, 0x00),
-            OctColor::Green => (0x00, 0xff, 0x00),
-            OctColor::Blue => (0x00, 0x00, 0xff),
-            OctColor::Red => (0xff, 0x00, 0x00),
-            OctColor::Yellow => (0xff, 0xff, 0x00),
-            OctColor::Orange => (0xff, 0x80, 0x00),
-            OctColor::HiZ => (0x80, 0x80, 0x80), /* looks greyish */
-        }
+        use embedded_graphics_core::prelude::RgbColor;
+        let rgb = OctColor::PALETTE.rgb(self).unwrap_or(Rgb888::new(0x80, 0x80, 0x80));
+        (rgb.r(), rgb.g(), rgb.b())
     }
 }
 //TODO: Rename get_bit_value to bit() and get_byte_value to byte() ?
@@ -229,6 +209,13 @@ impl From<u8> for Color {
 }
 
 impl TriColor {
+    /// All colors with their rgb values, used to convert `Rgb888`
+    pub const PALETTE: Palette<TriColor, 3> = Palette::new([
+        (TriColor::Black, Rgb888::new(0x00, 0x00, 0x00)),
+        (TriColor::White, Rgb888::new(0xff, 0xff, 0xff)),
+        (TriColor::Chromatic, Rgb888::new(0xff, 0x00, 0x00)),
+    ]);
+
     /// Get the color encoding of the color for one bit
     pub fn get_bit_value(self) -> u8 {
         match self {
@@ -248,7 +235,51 @@ impl TriColor {
 
 
 impl PixelColor for TriColor {
-    type Raw = ();
+    type Raw = RawU2;
+}
+
+/// 0 is black, 1 white, 2 and 3 are the chromatic color
+impl From<RawU2> for TriColor {
+    fn from(raw: RawU2) -> Self {
+        use embedded_graphics_core::prelude::RawData;
+        match raw.into_inner() {
+            0 => TriColor::Black,
+            1 => TriColor::White,
+            _ => TriColor::Chromatic,
+        }
+    }
+}
+
+impl From<TriColor> for RawU2 {
+    fn from(color: TriColor) -> Self {
+        RawU2::new(match color {
+            TriColor::Black => 0,
+            TriColor::White => 1,
+            TriColor::Chromatic => 2,
+        })
+    }
+}
+
+impl From<BinaryColor> for TriColor {
+    fn from(color: BinaryColor) -> Self {
+        match color {
+            BinaryColor::On => TriColor::Black,
+            BinaryColor::Off => TriColor::White,
+        }
+    }
+}
+
+/// The chromatic color is taken as red, which most three color panels use
+impl From<TriColor> for Rgb888 {
+    fn from(color: TriColor) -> Self {
+        TriColor::PALETTE.rgb(color).unwrap_or(Rgb888::new(0xff, 0xff, 0xff))
+    }
+}
+
+impl From<Rgb888> for TriColor {
+    fn from(p: Rgb888) -> Self {
+        TriColor::PALETTE.nearest(p)
+    }
 }
 
 ///定义颜色
@@ -271,8 +302,17 @@ impl Default for TwoBitColor {
 }
 
 impl TwoBitColor {
+    /// All shades with their rgb values, used to convert `Rgb888`
+    pub const PALETTE: Palette<TwoBitColor, 4> = Palette::new([
+        (TwoBitColor::Black, Rgb888::new(0x00, 0x00, 0x00)),
+        (TwoBitColor::Gray2, Rgb888::new(0x55, 0x55, 0x55)),
+        (TwoBitColor::Gray1, Rgb888::new(0xaa, 0xaa, 0xaa)),
+        (TwoBitColor::White, Rgb888::new(0xff, 0xff, 0xff)),
+    ])
+    .with_distance(Distance::Weighted);
 
     /// Gets a full byte of black or white pixels
+    #[deprecated(note = "the encoding depends on the controller, use `GrayEncoding::fill_pattern`")]
     pub fn get_byte_value(self) -> u8 {
         match self {
             TwoBitColor::White => 0x00,
@@ -305,6 +345,37 @@ impl TwoBitColor {
         self == TwoBitColor::Black
     }
 
+    /// Gray level from 0 (Black) to 3 (White), the order of the shades on the glass is
+    /// White > Gray1 > Gray2 > Black
+    pub fn level(self) -> u8 {
+        match self {
+            TwoBitColor::Black => 0,
+            TwoBitColor::Gray2 => 1,
+            TwoBitColor::Gray1 => 2,
+            TwoBitColor::White => 3,
+        }
+    }
+
+    /// Converts a gray level from 0 (Black) to 3 (White), higher bits are ignored
+    pub fn from_level(level: u8) -> Self {
+        match level & 0b11 {
+            0 => TwoBitColor::Black,
+            1 => TwoBitColor::Gray2,
+            2 => TwoBitColor::Gray1,
+            _ => TwoBitColor::White,
+        }
+    }
+
+    /// Luminance of the color from 0 (Black) to 255 (White)
+    pub fn luma(self) -> u8 {
+        self.level() * 0x55
+    }
+
+    /// Nearest color for a luminance from 0 (Black) to 255 (White)
+    pub fn from_luma(luma: u8) -> Self {
+        Self::from_level(((u16::from(luma) * 3 + 127) / 255) as u8)
+    }
+
 
 }
 
@@ -312,6 +383,225 @@ impl PixelColor for TwoBitColor {
     type Raw = RawU2;
 }
 
+/// Lookup table from the logical gray levels used by the app to the shades sent to the panel
+///
+/// Corrects panels where e.g. `Gray1` looks darker than `Gray2` without touching the app code.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GrayMap([TwoBitColor; 4]);
+
+impl Default for GrayMap {
+    fn default() -> Self {
+        GrayMap::IDENTITY
+    }
+}
+
+impl GrayMap {
+    /// Every color is drawn as itself
+    pub const IDENTITY: GrayMap = GrayMap([
+        TwoBitColor::Black,
+        TwoBitColor::Gray2,
+        TwoBitColor::Gray1,
+        TwoBitColor::White,
+    ]);
+
+    /// Creates a table from the shades drawn for the logical levels, darkest first
+    pub const fn new(
+        black: TwoBitColor,
+        gray2: TwoBitColor,
+        gray1: TwoBitColor,
+        white: TwoBitColor,
+    ) -> Self {
+        GrayMap([black, gray2, gray1, white])
+    }
+
+    /// Shade drawn for the logical `color`
+    pub fn map(self, color: TwoBitColor) -> TwoBitColor {
+        self.0[color.level() as usize]
+    }
+
+    /// First logical color drawn as `shade`, or `shade` itself if no color maps to it
+    pub fn unmap(self, shade: TwoBitColor) -> TwoBitColor {
+        (0..4)
+            .find(|level| self.0[*level as usize] == shade)
+            .map_or(shade, TwoBitColor::from_level)
+    }
+}
+
+/// The raw value is the gray level, so the data of `ImageRaw<Gray2>` can be used directly
+impl From<RawU2> for TwoBitColor {
+    fn from(raw: RawU2) -> Self {
+        use embedded_graphics_core::prelude::RawData;
+        Self::from_level(raw.into_inner())
+    }
+}
+
+impl From<TwoBitColor> for RawU2 {
+    fn from(color: TwoBitColor) -> Self {
+        RawU2::new(color.level())
+    }
+}
+
+impl From<Gray2> for TwoBitColor {
+    fn from(gray: Gray2) -> Self {
+        Self::from_level(gray.luma())
+    }
+}
+
+impl From<TwoBitColor> for Gray2 {
+    fn from(color: TwoBitColor) -> Self {
+        Gray2::new(color.level())
+    }
+}
+
+impl From<Gray8> for TwoBitColor {
+    fn from(gray: Gray8) -> Self {
+        Self::from_luma(gray.luma())
+    }
+}
+
+impl From<TwoBitColor> for Gray8 {
+    fn from(color: TwoBitColor) -> Self {
+        Gray8::new(color.luma())
+    }
+}
+
+impl From<BinaryColor> for TwoBitColor {
+    fn from(color: BinaryColor) -> Self {
+        match color {
+            BinaryColor::On => TwoBitColor::Black,
+            BinaryColor::Off => TwoBitColor::White,
+        }
+    }
+}
+
+/// Black and Gray2 are drawn as `BinaryColor::On` (black)
+impl From<TwoBitColor> for BinaryColor {
+    fn from(color: TwoBitColor) -> Self {
+        BinaryColor::from(color.level() < 2)
+    }
+}
+
+impl From<Rgb888> for TwoBitColor {
+    fn from(rgb: Rgb888) -> Self {
+        TwoBitColor::PALETTE.nearest(rgb)
+    }
+}
+
+impl From<TwoBitColor> for Rgb888 {
+    fn from(color: TwoBitColor) -> Self {
+        let luma = color.luma();
+        Rgb888::new(luma, luma, luma)
+    }
+}
+
+/// 16 gray levels of 4bpp controllers, level 0 is black and 15 white
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct FourBitGray(u8);
+
+impl FourBitGray {
+    /// Darkest level
+    pub const BLACK: FourBitGray = FourBitGray(0);
+    /// Brightest level
+    pub const WHITE: FourBitGray = FourBitGray(15);
+
+    /// Creates a gray from its level, only the lower 4 bits are used
+    pub const fn new(level: u8) -> Self {
+        FourBitGray(level & 0x0f)
+    }
+
+    /// Gray level from 0 (black) to 15 (white)
+    pub const fn level(self) -> u8 {
+        self.0
+    }
+
+    /// Luminance from 0 (black) to 255 (white)
+    pub const fn luma(self) -> u8 {
+        self.0 * 0x11
+    }
+
+    /// Nearest gray level of a luminance from 0 (black) to 255 (white)
+    pub const fn from_luma(luma: u8) -> Self {
+        FourBitGray(((luma as u16 * 15 + 127) / 255) as u8)
+    }
+}
+
+impl PixelColor for FourBitGray {
+    type Raw = RawU4;
+}
+
+impl From<RawU4> for FourBitGray {
+    fn from(raw: RawU4) -> Self {
+        use embedded_graphics_core::prelude::RawData;
+        FourBitGray::new(raw.into_inner())
+    }
+}
+
+impl From<FourBitGray> for RawU4 {
+    fn from(color: FourBitGray) -> Self {
+        RawU4::new(color.level())
+    }
+}
+
+impl From<Gray4> for FourBitGray {
+    fn from(gray: Gray4) -> Self {
+        FourBitGray::new(gray.luma())
+    }
+}
+
+impl From<FourBitGray> for Gray4 {
+    fn from(color: FourBitGray) -> Self {
+        Gray4::new(color.level())
+    }
+}
+
+impl From<Gray8> for FourBitGray {
+    fn from(gray: Gray8) -> Self {
+        FourBitGray::from_luma(gray.luma())
+    }
+}
+
+impl From<FourBitGray> for Gray8 {
+    fn from(color: FourBitGray) -> Self {
+        Gray8::new(color.luma())
+    }
+}
+
+/// Every 5th level is one of the 4 shades
+impl From<TwoBitColor> for FourBitGray {
+    fn from(color: TwoBitColor) -> Self {
+        FourBitGray(color.level() * 5)
+    }
+}
+
+impl From<FourBitGray> for TwoBitColor {
+    fn from(color: FourBitGray) -> Self {
+        TwoBitColor::from_level((color.level() + 2) / 5)
+    }
+}
+
+/// `BinaryColor::On` is black like on the other panels
+impl From<BinaryColor> for FourBitGray {
+    fn from(color: BinaryColor) -> Self {
+        match color {
+            BinaryColor::On => FourBitGray::BLACK,
+            BinaryColor::Off => FourBitGray::WHITE,
+        }
+    }
+}
+
+impl From<Rgb888> for FourBitGray {
+    fn from(rgb: Rgb888) -> Self {
+        Self::from(Gray8::from(rgb))
+    }
+}
+
+impl From<FourBitGray> for Rgb888 {
+    fn from(color: FourBitGray) -> Self {
+        let luma = color.luma();
+        Rgb888::new(luma, luma, luma)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -344,6 +634,79 @@ mod tests {
         assert_eq!(Color::from(1u8).get_bit_value(), 1u8);
     }
 
+    #[test]
+    fn two_bit_luminance_order() {
+        let colors = [
+            TwoBitColor::Black,
+            TwoBitColor::Gray2,
+            TwoBitColor::Gray1,
+            TwoBitColor::White,
+        ];
+        for pair in colors.windows(2) {
+            assert!(Gray8::from(pair[0]).luma() < Gray8::from(pair[1]).luma());
+        }
+    }
+
+    #[test]
+    fn two_bit_round_trips() {
+        use embedded_graphics::prelude::RgbColor;
+        for color in [
+            TwoBitColor::Black,
+            TwoBitColor::White,
+            TwoBitColor::Gray1,
+            TwoBitColor::Gray2,
+        ] {
+            assert_eq!(TwoBitColor::from(RawU2::from(color)), color);
+            assert_eq!(TwoBitColor::from(Gray2::from(color)), color);
+            assert_eq!(TwoBitColor::from(Gray8::from(color)), color);
+            assert_eq!(TwoBitColor::from(Rgb888::from(color)), color);
+        }
+        assert_eq!(TwoBitColor::from(BinaryColor::On), TwoBitColor::Black);
+        assert_eq!(BinaryColor::from(TwoBitColor::Gray1), BinaryColor::Off);
+        assert_eq!(TwoBitColor::from(Gray8::new(0x60)), TwoBitColor::Gray2);
+        assert_eq!(TwoBitColor::from(Rgb888::WHITE), TwoBitColor::White);
+    }
+
+    #[test]
+    fn gray_map_swaps_grays() {
+        let map = GrayMap::new(
+            TwoBitColor::Black,
+            TwoBitColor::Gray1,
+            TwoBitColor::Gray2,
+            TwoBitColor::White,
+        );
+        assert_eq!(map.map(TwoBitColor::Gray1), TwoBitColor::Gray2);
+        assert_eq!(map.unmap(map.map(TwoBitColor::Gray2)), TwoBitColor::Gray2);
+        assert_eq!(GrayMap::default().map(TwoBitColor::Gray1), TwoBitColor::Gray1);
+    }
+
+    #[test]
+    fn tri_color_raw_and_rgb() {
+        for color in [TriColor::Black, TriColor::White, TriColor::Chromatic] {
+            assert_eq!(TriColor::from(RawU2::from(color)), color);
+            assert_eq!(TriColor::from(Rgb888::from(color)), color);
+        }
+        assert_eq!(TriColor::from(Rgb888::new(200, 40, 30)), TriColor::Chromatic);
+        assert_eq!(TriColor::from(Rgb888::new(40, 40, 60)), TriColor::Black);
+    }
+
+    #[test]
+    fn four_bit_gray_conversions() {
+        for color in [
+            TwoBitColor::Black,
+            TwoBitColor::Gray2,
+            TwoBitColor::Gray1,
+            TwoBitColor::White,
+        ] {
+            assert_eq!(TwoBitColor::from(FourBitGray::from(color)), color);
+            assert_eq!(FourBitGray::from(color).luma(), color.luma());
+        }
+        assert_eq!(TwoBitColor::from(FourBitGray::new(7)), TwoBitColor::Gray2);
+        assert_eq!(FourBitGray::from(Gray8::new(0x78)), FourBitGray::new(7));
+        assert_eq!(Gray4::from(FourBitGray::new(9)), Gray4::new(9));
+        assert_eq!(FourBitGray::from(RawU4::from(FourBitGray::WHITE)), FourBitGray::WHITE);
+    }
+
     #[test]
     fn test_oct() {
         let left = OctColor::Red;
@@ -0,0 +1,266 @@
+//! Layered compositing of same sized framebuffers
+//!
+//! Every [Layer] is a full framebuffer (e.g. a `Display2in7`) which is drawn on independently.
+//! The [Compositor] merges the visible layers into the buffer of the display only when
+//! [Compositor::compose] is called, and only inside the area that changed since the last call.
+//!
+//! ```rust,ignore
+//! let mut compositor = Compositor::new(
+//!     [
+//!         Layer::new(Display2in7::default(), None),                      // background
+//!         Layer::new(Display2in7::default(), Some(TwoBitColor::White)), // popup
+//!     ],
+//!     TwoBitColor::White,
+//! );
+//! // ... draw into compositor.layer_mut(0) and compositor.layer_mut(1)
+//! compositor.layer_mut(1).set_visible(false);
+//! if let Some(area) = compositor.compose(&mut display)? {
+//!     // only `area` has to be sent to the lcd
+//! }
+//! ```
+
+use embedded_graphics::primitives::PointsIter;
+use embedded_graphics_core::image::GetPixel;
+use embedded_graphics_core::prelude::*;
+use embedded_graphics_core::primitives::Rectangle;
+
+/// Merges two optional areas into the smallest rectangle containing both
+pub(crate) fn union(a: Option<Rectangle>, b: Option<Rectangle>) -> Option<Rectangle> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let (a_br, b_br) = match (a.bottom_right(), b.bottom_right()) {
+                (Some(a_br), Some(b_br)) => (a_br, b_br),
+                (Some(_), None) => return Some(a),
+                (None, _) => return Some(b),
+            };
+            Some(Rectangle::with_corners(
+                a.top_left.component_min(b.top_left),
+                a_br.component_max(b_br),
+            ))
+        }
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// One plane of a [Compositor]
+///
+/// Pixels with the color of the transparency key let the layers below shine through,
+/// a layer without key is opaque.
+pub struct Layer<D: DrawTarget> {
+    display: D,
+    key: Option<D::Color>,
+    visible: bool,
+    /// Area which holds drawn content
+    bounds: Option<Rectangle>,
+    /// Area which changed since the last compose
+    dirty: Option<Rectangle>,
+}
+
+impl<D> Layer<D>
+where
+    D: DrawTarget + GetPixel<Color = <D as DrawTarget>::Color>,
+{
+    /// Creates a new visible layer, the whole layer is marked as dirty
+    pub fn new(display: D, key: Option<<D as DrawTarget>::Color>) -> Self {
+        let area = display.bounding_box();
+        Layer {
+            display,
+            key,
+            visible: true,
+            bounds: Some(area),
+            dirty: Some(area),
+        }
+    }
+
+    /// Shows or hides the layer
+    pub fn set_visible(&mut self, visible: bool) {
+        if self.visible != visible {
+            self.visible = visible;
+            self.dirty = union(self.dirty, self.bounds);
+        }
+    }
+
+    /// Is the layer shown
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Get the transparency key
+    pub fn key(&self) -> Option<<D as DrawTarget>::Color> {
+        self.key
+    }
+
+    /// Fills the whole layer with the transparency key, or `color` if the layer is opaque
+    pub fn clear_layer(&mut self, color: <D as DrawTarget>::Color) -> Result<(), D::Error> {
+        self.display.clear(self.key.unwrap_or(color))?;
+        self.dirty = union(self.dirty, self.bounds);
+        self.bounds = match self.key {
+            Some(_) => None,
+            None => Some(self.display.bounding_box()),
+        };
+        Ok(())
+    }
+
+    /// Marks an area as changed, needed after drawing through [Layer::display_mut]
+    pub fn mark_dirty(&mut self, area: Rectangle) {
+        let area = area.intersection(&self.display.bounding_box());
+        self.bounds = union(self.bounds, Some(area));
+        self.dirty = union(self.dirty, Some(area));
+    }
+
+    /// Returns the framebuffer of the layer
+    pub fn display(&self) -> &D {
+        &self.display
+    }
+
+    /// Returns the framebuffer of the layer, changes are not tracked
+    pub fn display_mut(&mut self) -> &mut D {
+        &mut self.display
+    }
+
+    /// Gets the color of this layer at `point`, `None` if it is transparent or hidden
+    fn visible_pixel(&self, point: Point) -> Option<<D as DrawTarget>::Color> {
+        if !self.visible {
+            return None;
+        }
+        self.display.pixel(point).filter(|color| Some(*color) != self.key)
+    }
+}
+
+impl<D: DrawTarget> Dimensions for Layer<D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.display.bounding_box()
+    }
+}
+
+impl<D> DrawTarget for Layer<D>
+where
+    D: DrawTarget + GetPixel<Color = <D as DrawTarget>::Color>,
+{
+    type Color = <D as DrawTarget>::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let area = self.display.bounding_box();
+        let mut changed: Option<Rectangle> = None;
+        let display = &mut self.display;
+        display.draw_iter(pixels.into_iter().inspect(|Pixel(point, _)| {
+            if area.contains(*point) {
+                changed = union(changed, Some(Rectangle::new(*point, Size::new(1, 1))));
+            }
+        }))?;
+        self.bounds = union(self.bounds, changed);
+        self.dirty = union(self.dirty, changed);
+        Ok(())
+    }
+}
+
+/// Merges `N` layers into a display, the last layer is on top
+pub struct Compositor<D: DrawTarget, const N: usize> {
+    layers: [Layer<D>; N],
+    background: D::Color,
+}
+
+impl<D, const N: usize> Compositor<D, N>
+where
+    D: DrawTarget + GetPixel<Color = <D as DrawTarget>::Color>,
+{
+    /// Creates a compositor, `background` is used where every layer is transparent
+    pub fn new(layers: [Layer<D>; N], background: <D as DrawTarget>::Color) -> Self {
+        Compositor { layers, background }
+    }
+
+    /// Returns the layer at `index`
+    pub fn layer(&self, index: usize) -> &Layer<D> {
+        &self.layers[index]
+    }
+
+    /// Returns the layer at `index` for drawing
+    pub fn layer_mut(&mut self, index: usize) -> &mut Layer<D> {
+        &mut self.layers[index]
+    }
+
+    /// Area which will be redrawn on the next [Compositor::compose]
+    pub fn dirty_area(&self) -> Option<Rectangle> {
+        self.layers
+            .iter()
+            .fold(None, |area, layer| union(area, layer.dirty))
+    }
+
+    /// Marks everything as dirty, e.g. after the display buffer was changed directly
+    pub fn invalidate(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.dirty = Some(layer.bounding_box());
+        }
+    }
+
+    /// Color of the composed image at `point`
+    fn composed_pixel(&self, point: Point) -> <D as DrawTarget>::Color {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.visible_pixel(point))
+            .unwrap_or(self.background)
+    }
+
+    /// Draws the changed area of all layers into `target`
+    ///
+    /// Returns the area which was redrawn, it can be used for a partial update of the lcd.
+    pub fn compose<T>(&mut self, target: &mut T) -> Result<Option<Rectangle>, T::Error>
+    where
+        T: DrawTarget<Color = <D as DrawTarget>::Color>,
+    {
+        let area = match self.dirty_area() {
+            Some(area) => area.intersection(&target.bounding_box()),
+            None => return Ok(None),
+        };
+        target.fill_contiguous(&area, area.points().map(|point| self.composed_pixel(point)))?;
+        for layer in self.layers.iter_mut() {
+            layer.dirty = None;
+        }
+        Ok(Some(area))
+    }
+}
+
+#[cfg(all(test, feature = "uc1638"))]
+mod tests {
+    use super::*;
+    use crate::color::TwoBitColor;
+    use crate::uc1638::prelude::Display2in7;
+    use embedded_graphics::primitives::{Primitive, PrimitiveStyle};
+
+    #[test]
+    fn popup_hides_and_restores_background() {
+        let mut compositor = Compositor::new(
+            [
+                Layer::new(Display2in7::default(), None),
+                Layer::new(Display2in7::default(), Some(TwoBitColor::White)),
+            ],
+            TwoBitColor::White,
+        );
+        let mut display = Display2in7::default();
+        let _ = compositor.layer_mut(1).clear_layer(TwoBitColor::White);
+        let _ = Rectangle::new(Point::new(0, 0), Size::new(20, 20))
+            .into_styled(PrimitiveStyle::with_fill(TwoBitColor::Gray1))
+            .draw(compositor.layer_mut(0));
+        let _ = Rectangle::new(Point::new(10, 10), Size::new(4, 4))
+            .into_styled(PrimitiveStyle::with_fill(TwoBitColor::Black))
+            .draw(compositor.layer_mut(1));
+        let _ = compositor.compose(&mut display);
+        assert_eq!(display.pixel(Point::new(5, 5)), Some(TwoBitColor::Gray1));
+        assert_eq!(display.pixel(Point::new(11, 11)), Some(TwoBitColor::Black));
+        assert_eq!(compositor.dirty_area(), None);
+
+        compositor.layer_mut(1).set_visible(false);
+        assert_eq!(
+            compositor.dirty_area(),
+            Some(Rectangle::new(Point::new(10, 10), Size::new(4, 4)))
+        );
+        let _ = compositor.compose(&mut display);
+        assert_eq!(display.pixel(Point::new(11, 11)), Some(TwoBitColor::Gray1));
+    }
+}
@@ -0,0 +1,321 @@
+//! Dithering of gray and rgb images into the few gray levels of the panels
+//!
+//! [DitherTarget] wraps any framebuffer of the crate and accepts `Gray8`, `Rgb565`, `Rgb888` or
+//! any other color which converts into `Gray8`, so widgets rendering in rgb run unchanged on the
+//! gray and mono panels. Error diffusion needs the pixels in row order,
+//! it is used for everything drawn with `fill_contiguous` and `fill_solid` (images, filled
+//! shapes). Single pixels from `draw_iter` fall back to the 4x4 Bayer matrix.
+//!
+//! ```rust,ignore
+//! let mut display = Display2in3::default();
+//! let mut dithered: DitherTarget<_, Gray8, 128> =
+//!     DitherTarget::new(&mut display, Dither::FloydSteinberg);
+//! Image::new(&raw_gray8_image, Point::zero()).draw(&mut dithered)?;
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_graphics::primitives::PointsIter;
+use embedded_graphics_core::pixelcolor::{BinaryColor, Gray8, GrayColor};
+use embedded_graphics_core::prelude::*;
+use embedded_graphics_core::primitives::Rectangle;
+
+use crate::color::{FourBitGray, TwoBitColor};
+
+/// Colors with evenly spaced gray levels, level 0 is black
+pub trait GrayLevels: PixelColor {
+    /// Number of gray levels
+    const LEVELS: u8;
+
+    /// Color of a level from 0 (black) to `LEVELS - 1` (white)
+    fn from_gray_level(level: u8) -> Self;
+
+    /// Nearest color for a luminance from 0 (black) to 255 (white)
+    fn from_luma(luma: u8) -> Self {
+        let max = u16::from(Self::LEVELS - 1);
+        Self::from_gray_level(((u16::from(luma) * max + 127) / 255) as u8)
+    }
+}
+
+impl GrayLevels for TwoBitColor {
+    const LEVELS: u8 = 4;
+
+    fn from_gray_level(level: u8) -> Self {
+        TwoBitColor::from_level(level)
+    }
+}
+
+impl GrayLevels for FourBitGray {
+    const LEVELS: u8 = 16;
+
+    fn from_gray_level(level: u8) -> Self {
+        FourBitGray::new(level)
+    }
+}
+
+/// `BinaryColor::On` is black on all panels of the crate
+impl GrayLevels for BinaryColor {
+    const LEVELS: u8 = 2;
+
+    fn from_gray_level(level: u8) -> Self {
+        BinaryColor::from(level == 0)
+    }
+}
+
+/// Dithering algorithm
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dither {
+    /// Black below the luminance, white from it on, no grays
+    Threshold(u8),
+    /// No dithering, nearest gray level
+    Nearest,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer4,
+    /// Ordered dithering with a 8x8 Bayer matrix
+    Bayer8,
+    /// Floyd–Steinberg error diffusion
+    FloydSteinberg,
+    /// Atkinson error diffusion, only 3/4 of the error is spread which keeps more contrast
+    Atkinson,
+}
+
+#[rustfmt::skip]
+const BAYER8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Quantizes `value` to the nearest level of `T`, returns the color and the remaining error
+fn quantize<T: GrayLevels>(value: i16) -> (T, i16) {
+    let max = i16::from(T::LEVELS - 1);
+    let level = (value.clamp(0, 255) * max + 127) / 255;
+    (T::from_gray_level(level as u8), value - level * 255 / max)
+}
+
+/// Ordered dithering of one pixel, `size` is 4 or 8
+fn ordered<T: GrayLevels>(luma: u8, point: Point, size: i32) -> T {
+    let (x, y) = (point.x.rem_euclid(size) as usize, point.y.rem_euclid(size) as usize);
+    // the 4x4 matrix is the top left corner of the 8x8 one scaled by 4
+    let (threshold, cells) = if size == 4 {
+        (i16::from(BAYER8[y][x] / 4), 16)
+    } else {
+        (i16::from(BAYER8[y][x]), 64)
+    };
+    let step = 255 / i16::from(T::LEVELS - 1);
+    let offset = (2 * threshold + 1) * step / (2 * cells) - step / 2;
+    quantize::<T>(i16::from(luma) + offset).0
+}
+
+/// Dithers one pixel without knowledge of its neighbours
+fn single<T, C>(method: Dither, Pixel(point, color): Pixel<C>) -> Pixel<T>
+where
+    T: GrayLevels,
+    C: PixelColor + Into<Gray8>,
+{
+    let luma = color.into().luma();
+    let color = match method {
+        Dither::Threshold(threshold) => {
+            T::from_gray_level(if luma >= threshold { T::LEVELS - 1 } else { 0 })
+        }
+        Dither::Nearest => T::from_luma(luma),
+        Dither::Bayer8 => ordered(luma, point, 8),
+        Dither::Bayer4 | Dither::FloydSteinberg | Dither::Atkinson => ordered(luma, point, 4),
+    };
+    Pixel(point, color)
+}
+
+/// DrawTarget which dithers `Gray8`/`Rgb888` colors into the colors of a framebuffer
+///
+/// `W` is the widest area which is error diffused, usually the width of the display.
+/// The error is kept in three lines of `W` values.
+pub struct DitherTarget<'a, D, C, const W: usize> {
+    target: &'a mut D,
+    method: Dither,
+    errors: [[i16; W]; 3],
+    _color: PhantomData<C>,
+}
+
+impl<'a, D, C, const W: usize> DitherTarget<'a, D, C, W>
+where
+    D: DrawTarget,
+    D::Color: GrayLevels,
+    C: PixelColor + Into<Gray8>,
+{
+    /// Wraps `target`, everything drawn is dithered with `method`
+    pub fn new(target: &'a mut D, method: Dither) -> Self {
+        DitherTarget {
+            target,
+            method,
+            errors: [[0; W]; 3],
+            _color: PhantomData,
+        }
+    }
+
+    /// Changes the dithering algorithm
+    pub fn set_method(&mut self, method: Dither) {
+        self.method = method;
+    }
+
+    /// Spreads `error` of the pixel in column `x` to its neighbours
+    fn diffuse(&mut self, x: usize, error: i16) {
+        let add = |row: &mut [i16; W], x: Option<usize>, value: i16| {
+            if let Some(cell) = x.and_then(|x| row.get_mut(x)) {
+                *cell += value;
+            }
+        };
+        let [current, next, after] = &mut self.errors;
+        match self.method {
+            Dither::FloydSteinberg => {
+                add(current, Some(x + 1), error * 7 / 16);
+                add(next, x.checked_sub(1), error * 3 / 16);
+                add(next, Some(x), error * 5 / 16);
+                add(next, Some(x + 1), error / 16);
+            }
+            Dither::Atkinson => {
+                let part = error / 8;
+                add(current, Some(x + 1), part);
+                add(current, Some(x + 2), part);
+                add(next, x.checked_sub(1), part);
+                add(next, Some(x), part);
+                add(next, Some(x + 1), part);
+                add(after, Some(x), part);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, D, C, const W: usize> Dimensions for DitherTarget<'a, D, C, W>
+where
+    D: DrawTarget,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<'a, D, C, const W: usize> DrawTarget for DitherTarget<'a, D, C, W>
+where
+    D: DrawTarget,
+    D::Color: GrayLevels,
+    C: PixelColor + Into<Gray8>,
+{
+    type Color = C;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let method = self.method;
+        let pixels = pixels.into_iter().map(|pixel| single(method, pixel));
+        self.target.draw_iter(pixels)
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        if !matches!(self.method, Dither::FloydSteinberg | Dither::Atkinson) {
+            let method = self.method;
+            let pixels = area
+                .points()
+                .zip(colors)
+                .map(|(point, color)| single(method, Pixel(point, color)));
+            return self.target.draw_iter(pixels);
+        }
+
+        self.errors = [[0; W]; 3];
+        let width = area.size.width as usize;
+        let mut colors = colors.into_iter();
+        for y in 0..area.size.height as i32 {
+            for x in 0..width {
+                let color = match colors.next() {
+                    Some(color) => color,
+                    None => return Ok(()),
+                };
+                let point = area.top_left + Point::new(x as i32, y);
+                let luma = i16::from(color.into().luma());
+                let value = luma + self.errors[0].get(x).copied().unwrap_or(0);
+                let (color, error) = quantize::<D::Color>(value);
+                if x < W {
+                    self.diffuse(x, error);
+                }
+                self.target.draw_iter(core::iter::once(Pixel(point, color)))?;
+            }
+            self.errors.rotate_left(1);
+            self.errors[2] = [0; W];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "uc1638"))]
+mod tests {
+    use super::*;
+    use crate::uc1638::prelude::Display2in7;
+    use embedded_graphics::image::GetPixel;
+    use crate::graphics::{Display, VarDisplay};
+    use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+
+    fn mean_luma(display: &Display2in7, area: Rectangle) -> u32 {
+        let sum: u32 = area
+            .points()
+            .map(|point| u32::from(display.pixel(point).unwrap().luma()))
+            .sum();
+        sum / area.size.width / area.size.height
+    }
+
+    #[test]
+    fn dithered_gray_keeps_mean_luminance() {
+        let area = Rectangle::new(Point::new(0, 0), Size::new(16, 16));
+        for method in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Bayer4, Dither::Bayer8] {
+            let mut display = Display2in7::default();
+            let mut dithered: DitherTarget<_, Gray8, 240> =
+                DitherTarget::new(&mut display, method);
+            let _ = dithered.fill_solid(&area, Gray8::new(128));
+            let mean = mean_luma(&display, area);
+            assert!((118..=138).contains(&mean), "{:?} {}", method, mean);
+        }
+    }
+
+    #[test]
+    fn rgb565_on_gray_and_mono_buffers() {
+        let mut display = Display2in7::default();
+        let mut adapted: DitherTarget<_, Rgb565, 240> =
+            DitherTarget::new(&mut display, Dither::Threshold(100));
+        let _ = Pixel(Point::new(0, 0), Rgb565::new(10, 20, 10)).draw(&mut adapted);
+        let _ = Pixel(Point::new(1, 0), Rgb565::new(16, 32, 16)).draw(&mut adapted);
+        assert_eq!(display.pixel(Point::new(0, 0)), Some(TwoBitColor::Black));
+        assert_eq!(display.pixel(Point::new(1, 0)), Some(TwoBitColor::White));
+
+        let mut buffer = [0xffu8; 2 * 2];
+        let mut mono = VarDisplay::new(16, 2, &mut buffer);
+        let mut adapted: DitherTarget<_, Rgb565, 16> =
+            DitherTarget::new(&mut mono, Dither::Nearest);
+        let _ = Pixel(Point::new(0, 0), Rgb565::WHITE).draw(&mut adapted);
+        let _ = Pixel(Point::new(1, 0), Rgb565::new(2, 4, 2)).draw(&mut adapted);
+        // On (black) clears the bit
+        assert_eq!(mono.buffer()[0], 0b1011_1111);
+    }
+
+    #[test]
+    fn nearest_maps_to_closest_level() {
+        let mut display = Display2in7::default();
+        let mut dithered: DitherTarget<_, Gray8, 240> =
+            DitherTarget::new(&mut display, Dither::Nearest);
+        let _ = Pixel(Point::new(1, 1), Gray8::new(80)).draw(&mut dithered);
+        assert_eq!(display.pixel(Point::new(1, 1)), Some(TwoBitColor::Gray2));
+
+        let mut dithered: DitherTarget<_, Rgb888, 240> =
+            DitherTarget::new(&mut display, Dither::Nearest);
+        let _ = Pixel(Point::new(1, 1), Rgb888::new(180, 180, 160)).draw(&mut dithered);
+        assert_eq!(display.pixel(Point::new(1, 1)), Some(TwoBitColor::Gray1));
+    }
+}
@@ -0,0 +1,37 @@
+//! Commands of the UC8151 controller of the 2.9" b/w/red e-paper
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub(crate) enum Command {
+    /// Panel setting: resolution, scan direction, booster
+    PanelSetting = 0x00,
+    /// Turns the booster and the source/gate drivers off
+    PowerOff = 0x02,
+    /// Turns the booster and the source/gate drivers on
+    PowerOn = 0x04,
+    /// Enters deep sleep, needs the check code 0xA5
+    DeepSleep = 0x07,
+    /// Writes the black/white frame
+    DataStartTransmission1 = 0x10,
+    /// Starts the refresh of the panel with the frame RAM
+    DisplayRefresh = 0x12,
+    /// Writes the red frame
+    DataStartTransmission2 = 0x13,
+    /// Border color and the interval between data and refresh
+    VcomAndDataIntervalSetting = 0x50,
+    /// Width and height of the panel
+    ResolutionSetting = 0x61,
+    /// Window for partial transfers
+    PartialWindow = 0x90,
+    /// Restricts the following transfers to the partial window
+    PartialIn = 0x91,
+    /// Ends the partial window
+    PartialOut = 0x92,
+}
+
+impl crate::Command for Command {
+    /// Returns the address of the command
+    fn address(self) -> u8 {
+        self as u8
+    }
+}
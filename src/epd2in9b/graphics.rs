@@ -0,0 +1,93 @@
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::geometry::{OriginDimensions, Size};
+use embedded_graphics::Pixel;
+use crate::color::TriColor;
+use crate::epd2in9b::{HEIGHT, WIDTH};
+use crate::graphics::{DisplayRotation, TriDisplay};
+
+const LAYER_LEN: usize = WIDTH as usize * HEIGHT as usize / 8;
+
+/// Full size buffer for the 2.9" b/w/red e-paper, the black layer followed by the red layer
+pub struct Display2in9b {
+    buffer: [u8; 2 * LAYER_LEN],
+    rotation: DisplayRotation,
+}
+
+impl Default for Display2in9b {
+    fn default() -> Self {
+        Display2in9b {
+            buffer: [TriColor::White.get_byte_value(); 2 * LAYER_LEN],
+            rotation: DisplayRotation::default(),
+        }
+    }
+}
+
+impl DrawTarget for Display2in9b {
+    type Color = TriColor;
+    type Error = core::convert::Infallible;
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            self.draw_helper_tri(WIDTH, HEIGHT, pixel)?;
+        }
+        Ok(())
+    }
+}
+
+impl OriginDimensions for Display2in9b {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl TriDisplay for Display2in9b {
+    fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn chromatic_offset(&self) -> usize {
+        LAYER_LEN
+    }
+
+    fn bw_buffer(&self) -> &[u8] {
+        &self.buffer[..LAYER_LEN]
+    }
+
+    fn chromatic_buffer(&self) -> &[u8] {
+        &self.buffer[LAYER_LEN..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::image::{Image, ImageRaw};
+    use embedded_graphics::prelude::*;
+
+    #[test]
+    fn raw_image_fills_both_layers() {
+        let mut display = Display2in9b::default();
+        // one row of 4 pixels: black, white, red, red
+        let data = [0b00_01_10_11];
+        let raw = ImageRaw::<TriColor>::new(&data, 4);
+        let _ = Image::new(&raw, Point::zero()).draw(&mut display);
+
+        assert_eq!(display.bw_buffer()[0], 0b0111_1111);
+        assert_eq!(display.chromatic_buffer()[0], 0b1100_1111);
+        assert_eq!(display.buffer().len(), 2 * 128 * 296 / 8);
+    }
+}
@@ -0,0 +1,348 @@
+//! Driver for the Waveshare 2.9" b/w/red e-paper (V3, UC8151 controller)
+//!
+//! The panel is 128x296 pixels with a black and a red layer. Drawing is done on a
+//! [Display2in9b](prelude::Display2in9b) with [TriColor], its whole buffer is sent
+//! with `update_frame`.
+//!
+//! E-paper refreshes take seconds, the driver waits for the BUSY pin of the panel if one is
+//! given with [Epd2in9b::with_busy], otherwise it waits the typical time of each step.
+//! `new` picks [NoBusy](prelude::NoBusy) for the BUSY pin.
+
+use embedded_hal::{
+    blocking::{delay::*, spi::Write},
+    digital::v2::*,
+};
+
+use crate::color::TriColor;
+use crate::interface::{DisplayInterface, NoBusy};
+use crate::traits::{InternalWiAdditions, WaveshareDisplay, WaveshareThreeColorDisplay};
+use command::Command;
+
+/// Width of the display
+pub const WIDTH: u32 = 128;
+/// Height of the display
+pub const HEIGHT: u32 = 296;
+/// Default Background Color
+pub const DEFAULT_BACKGROUND_COLOR: TriColor = TriColor::White;
+
+const LAYER_LEN: u32 = WIDTH * HEIGHT / 8;
+/// Typical duration of a full refresh, waited without a BUSY pin
+const REFRESH_MS: u32 = 15_000;
+/// Longest wait for a full refresh
+const REFRESH_TIMEOUT_MS: u32 = 20_000;
+/// Typical duration of power on and off, waited without a BUSY pin
+const POWER_MS: u32 = 100;
+/// Longest wait after power on and off
+const POWER_TIMEOUT_MS: u32 = 200;
+
+mod command;
+mod graphics;
+
+/// Framebuffer, color and traits needed to drive the panel
+pub mod prelude {
+    pub use crate::epd2in9b::graphics::Display2in9b;
+
+    pub use crate::interface::NoBusy;
+    pub use crate::color::TriColor;
+    pub use crate::graphics::TriDisplay;
+    pub use crate::traits::{WaveshareDisplay, WaveshareThreeColorDisplay};
+}
+
+/// Epd2in9b driver
+///
+pub struct Epd2in9b<SPI, CS, BUSY, DC, RST, DELAY> {
+    /// Connection Interface
+    interface: DisplayInterface<SPI, CS, DC, RST, DELAY>,
+    /// BUSY pin, low while the panel is busy
+    busy: Option<BUSY>,
+    /// Background Color
+    color: TriColor,
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> InternalWiAdditions<SPI, CS, DC, RST, DELAY>
+for Epd2in9b<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    fn init(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.interface.reset(delay, 10);
+
+        self.command(spi, Command::PowerOn)?;
+        self.wait_until_idle(delay, POWER_MS, POWER_TIMEOUT_MS);
+
+        // b/w/red mode, scan up and right, booster on
+        self.cmd_with_data(spi, Command::PanelSetting, &[0x0F, 0x89])?;
+        self.send_resolution(spi)?;
+        // white border, default data interval
+        self.cmd_with_data(spi, Command::VcomAndDataIntervalSetting, &[0x77])?;
+        Ok(())
+    }
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> WaveshareDisplay<SPI, CS, DC, RST, DELAY>
+for Epd2in9b<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    type DisplayColor = TriColor;
+
+    /// Creates the driver without a BUSY pin, see [Epd2in9b::with_busy]
+    fn new(spi: &mut SPI, cs: CS, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(cs, dc, rst);
+        let mut epd = Epd2in9b { interface, busy: None, color: DEFAULT_BACKGROUND_COLOR };
+
+        epd.init(spi, delay)?;
+
+        Ok(epd)
+    }
+
+    /// Sends the black layer, and the red layer too if `buffer` holds both
+    /// like the buffer of [Display2in9b](prelude::Display2in9b)
+    fn update_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        _delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        let (black, chromatic) = buffer.split_at(buffer.len().min(LAYER_LEN as usize));
+        self.update_achromatic_frame(spi, black)?;
+        if chromatic.is_empty() {
+            self.command(spi, Command::DataStartTransmission2)?;
+            self.interface.data_x_times(spi, TriColor::White.get_byte_value(), LAYER_LEN)
+        } else {
+            self.update_chromatic_frame(spi, chromatic)
+        }
+    }
+
+    /// Sends the black layer of a window, `x` and `width` are rounded to multiples of 8
+    fn update_partial_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), SPI::Error> {
+        self.command(spi, Command::PartialIn)?;
+        self.command(spi, Command::PartialWindow)?;
+        self.shift_display(spi, x, y, width, height)?;
+        self.cmd_with_data(spi, Command::DataStartTransmission1, buffer)?;
+        self.command(spi, Command::PartialOut)
+    }
+
+    fn display_frame(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::DisplayRefresh)?;
+        self.wait_until_idle(delay, REFRESH_MS, REFRESH_TIMEOUT_MS);
+        Ok(())
+    }
+
+    fn update_and_display_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        self.update_frame(spi, buffer, delay)?;
+        self.display_frame(spi, delay)
+    }
+
+    fn clear_frame(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        let black = match self.color {
+            TriColor::Black => 0x00,
+            TriColor::White | TriColor::Chromatic => 0xFF,
+        };
+        let chromatic = match self.color {
+            TriColor::Chromatic => 0x00,
+            TriColor::Black | TriColor::White => 0xFF,
+        };
+
+        self.command(spi, Command::DataStartTransmission1)?;
+        self.interface.data_x_times(spi, black, LAYER_LEN)?;
+        self.command(spi, Command::DataStartTransmission2)?;
+        self.interface.data_x_times(spi, chromatic, LAYER_LEN)?;
+        self.display_frame(spi, delay)
+    }
+
+    fn set_background_color(&mut self, color: TriColor) {
+        self.color = color;
+    }
+
+    fn background_color(&self) -> &TriColor {
+        &self.color
+    }
+
+    fn width(&self) -> u32 {
+        WIDTH
+    }
+
+    fn height(&self) -> u32 {
+        HEIGHT
+    }
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> WaveshareThreeColorDisplay<SPI, CS, DC, RST, DELAY>
+for Epd2in9b<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    fn update_color_frame(
+        &mut self,
+        spi: &mut SPI,
+        black: &[u8],
+        chromatic: &[u8],
+    ) -> Result<(), SPI::Error> {
+        self.update_achromatic_frame(spi, black)?;
+        self.update_chromatic_frame(spi, chromatic)
+    }
+
+    fn update_achromatic_frame(&mut self, spi: &mut SPI, black: &[u8]) -> Result<(), SPI::Error> {
+        self.command(spi, Command::DataStartTransmission1)?;
+        self.interface.data_all(spi, black)
+    }
+
+    fn update_chromatic_frame(
+        &mut self,
+        spi: &mut SPI,
+        chromatic: &[u8],
+    ) -> Result<(), SPI::Error> {
+        self.command(spi, Command::DataStartTransmission2)?;
+        self.interface.data_all(spi, chromatic)
+    }
+}
+
+impl<SPI, CS, DC, RST, DELAY> Epd2in9b<SPI, CS, NoBusy, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    /// Creates the driver without a BUSY pin, see [Epd2in9b::with_busy]
+    pub fn new(spi: &mut SPI, cs: CS, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
+        <Self as WaveshareDisplay<SPI, CS, DC, RST, DELAY>>::new(spi, cs, dc, rst, delay)
+    }
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> Epd2in9b<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    /// Creates the driver with the BUSY pin of the panel, which is low while it refreshes
+    pub fn with_busy(
+        spi: &mut SPI,
+        cs: CS,
+        busy: BUSY,
+        dc: DC,
+        rst: RST,
+        delay: &mut DELAY,
+    ) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(cs, dc, rst);
+        let mut epd = Epd2in9b { interface, busy: Some(busy), color: DEFAULT_BACKGROUND_COLOR };
+
+        epd.init(spi, delay)?;
+
+        Ok(epd)
+    }
+
+    /// Turns the panel off and puts the controller into deep sleep, the image stays visible
+    ///
+    /// A reset is needed to wake it up, `new`/`with_busy` again or call `wake_up`.
+    pub fn sleep(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::PowerOff)?;
+        self.wait_until_idle(delay, POWER_MS, POWER_TIMEOUT_MS);
+        self.cmd_with_data(spi, Command::DeepSleep, &[0xA5])
+    }
+
+    /// Resets and initialises the panel after [Epd2in9b::sleep]
+    pub fn wake_up(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.init(spi, delay)
+    }
+
+    /// Waits until the BUSY pin goes high for at most `timeout_ms`, or `typical_ms` without a BUSY pin
+    fn wait_until_idle(&mut self, delay: &mut DELAY, typical_ms: u32, timeout_ms: u32) {
+        let busy = match &self.busy {
+            Some(busy) => busy,
+            None => {
+                for _ in 0..typical_ms / 10 {
+                    delay.delay_ms(10);
+                }
+                return;
+            }
+        };
+        let mut waited = 0;
+        while waited < timeout_ms && busy.is_low().unwrap_or(false) {
+            delay.delay_ms(10);
+            waited += 10;
+        }
+    }
+
+    fn command(&mut self, spi: &mut SPI, command: Command) -> Result<(), SPI::Error> {
+        self.interface.cmd(spi, command)
+    }
+
+    fn cmd_with_data(
+        &mut self,
+        spi: &mut SPI,
+        command: Command,
+        data: &[u8],
+    ) -> Result<(), SPI::Error> {
+        self.interface.cmd_with_data(spi, command, data)
+    }
+
+    fn send_resolution(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.cmd_with_data(
+            spi,
+            Command::ResolutionSetting,
+            &[WIDTH as u8, (HEIGHT >> 8) as u8, HEIGHT as u8],
+        )
+    }
+
+    /// Sends the partial window, x is rounded down and the end up to a multiple of 8
+    fn shift_display(
+        &mut self,
+        spi: &mut SPI,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), SPI::Error> {
+        let x_start = x & 0xf8;
+        let x_end = (x + width - 1) | 0x07;
+        let y_end = y + height - 1;
+        self.interface.data(
+            spi,
+            &[
+                x_start as u8,
+                x_end as u8,
+                (y >> 8) as u8,
+                y as u8,
+                (y_end >> 8) as u8,
+                y_end as u8,
+                // gates scan both inside and outside of the partial window
+                0x01,
+            ],
+        )
+    }
+}
@@ -0,0 +1,43 @@
+//! Commands of the controller of the 5.65" 7 color e-paper
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub(crate) enum Command {
+    /// Panel setting: scan direction, booster
+    PanelSetting = 0x00,
+    /// Source and gate voltages
+    PowerSetting = 0x01,
+    /// Turns the booster and the source/gate drivers off
+    PowerOff = 0x02,
+    /// Delay between the power phases
+    PowerOffSequenceSetting = 0x03,
+    /// Turns the booster and the source/gate drivers on
+    PowerOn = 0x04,
+    /// Soft start of the booster
+    BoosterSoftStart = 0x06,
+    /// Enters deep sleep, needs the check code 0xA5
+    DeepSleep = 0x07,
+    /// Writes the frame, one nibble per pixel
+    DataStartTransmission1 = 0x10,
+    /// Starts the refresh of the panel with the frame RAM
+    DisplayRefresh = 0x12,
+    /// Frame rate of the waveform
+    PllControl = 0x30,
+    /// Temperature sensor selection
+    TemperatureSensor = 0x41,
+    /// Border color and the interval between data and refresh
+    VcomAndDataIntervalSetting = 0x50,
+    /// Non overlap period of gate and source
+    TconSetting = 0x60,
+    /// Width and height of the panel
+    ResolutionSetting = 0x61,
+    /// Power saving of the source drivers
+    PowerSaving = 0xE3,
+}
+
+impl crate::Command for Command {
+    /// Returns the address of the command
+    fn address(self) -> u8 {
+        self as u8
+    }
+}
@@ -0,0 +1,390 @@
+//! Driver for the Waveshare 5.65" 7 color e-paper (ACeP)
+//!
+//! The panel is 600x448 pixels, each pixel is one [OctColor] nibble. Drawing is done on a
+//! [Display5in65f](prelude::Display5in65f), its buffer is sent with `update_frame`.
+//!
+//! A refresh takes about 15 seconds, the driver waits for the BUSY pin of the panel if one is
+//! given with [Epd5in65f::with_busy], otherwise it waits the typical time of each step.
+//! `new` picks [NoBusy](prelude::NoBusy) for the BUSY pin.
+
+use embedded_hal::{
+    blocking::{delay::*, spi::Write},
+    digital::v2::*,
+};
+
+use crate::color::OctColor;
+use crate::interface::{DisplayInterface, NoBusy};
+use crate::traits::{InternalWiAdditions, WaveshareDisplay};
+use command::Command;
+
+/// Width of the display
+pub const WIDTH: u32 = 600;
+/// Height of the display
+pub const HEIGHT: u32 = 448;
+/// Default Background Color
+pub const DEFAULT_BACKGROUND_COLOR: OctColor = OctColor::White;
+
+/// Typical duration of a full refresh, waited without a BUSY pin
+const REFRESH_MS: u32 = 15_000;
+/// Longest wait for a full refresh
+const REFRESH_TIMEOUT_MS: u32 = 30_000;
+/// Typical duration of power on and off, waited without a BUSY pin
+const POWER_MS: u32 = 100;
+/// Longest wait after reset, power on and off
+const POWER_TIMEOUT_MS: u32 = 500;
+
+mod command;
+mod graphics;
+
+/// Framebuffer, color and traits needed to drive the panel
+pub mod prelude {
+    pub use crate::epd5in65f::graphics::Display5in65f;
+
+    pub use crate::interface::NoBusy;
+    pub use crate::color::OctColor;
+    pub use crate::graphics::OctDisplay;
+    pub use crate::traits::WaveshareDisplay;
+}
+
+/// Epd5in65f driver
+///
+pub struct Epd5in65f<SPI, CS, BUSY, DC, RST, DELAY> {
+    /// Connection Interface
+    interface: DisplayInterface<SPI, CS, DC, RST, DELAY>,
+    /// BUSY pin, low while the panel is busy
+    busy: Option<BUSY>,
+    /// Background Color
+    color: OctColor,
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> InternalWiAdditions<SPI, CS, DC, RST, DELAY>
+for Epd5in65f<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    fn init(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.interface.reset(delay, 2);
+        self.wait_until_idle(delay, POWER_MS, POWER_TIMEOUT_MS);
+
+        self.cmd_with_data(spi, Command::PanelSetting, &[0xEF, 0x08])?;
+        self.cmd_with_data(spi, Command::PowerSetting, &[0x37, 0x00, 0x23, 0x23])?;
+        self.cmd_with_data(spi, Command::PowerOffSequenceSetting, &[0x00])?;
+        self.cmd_with_data(spi, Command::BoosterSoftStart, &[0xC7, 0xC7, 0x1D])?;
+        self.cmd_with_data(spi, Command::PllControl, &[0x3C])?;
+        self.cmd_with_data(spi, Command::TemperatureSensor, &[0x00])?;
+        self.cmd_with_data(spi, Command::VcomAndDataIntervalSetting, &[0x37])?;
+        self.cmd_with_data(spi, Command::TconSetting, &[0x22])?;
+        self.send_resolution(spi)?;
+        self.cmd_with_data(spi, Command::PowerSaving, &[0xAA])?;
+        delay.delay_ms(100);
+        self.cmd_with_data(spi, Command::VcomAndDataIntervalSetting, &[0x37])?;
+        Ok(())
+    }
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> WaveshareDisplay<SPI, CS, DC, RST, DELAY>
+for Epd5in65f<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    type DisplayColor = OctColor;
+
+    /// Creates the driver without a BUSY pin, see [Epd5in65f::with_busy]
+    fn new(spi: &mut SPI, cs: CS, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(cs, dc, rst);
+        let mut epd = Epd5in65f { interface, busy: None, color: DEFAULT_BACKGROUND_COLOR };
+
+        epd.init(spi, delay)?;
+
+        Ok(epd)
+    }
+
+    fn update_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        _delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        self.send_resolution(spi)?;
+        self.cmd_with_data(spi, Command::DataStartTransmission1, buffer)
+    }
+
+    /// Sends a window of `width` x `height` pixels at `x`, `y`, rows of `buffer` start on a byte
+    ///
+    /// The controller has no RAM window, so the whole frame is sent with the background color
+    /// around the window.
+    fn update_partial_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), SPI::Error> {
+        let background = self.color.get_nibble();
+        let stride = width.div_ceil(2);
+        let (x_end, y_end) = (x.saturating_add(width), y.saturating_add(height));
+        let nibble = |px: u32, py: u32| {
+            if px < x || px >= x_end || py < y || py >= y_end {
+                return background;
+            }
+            let (col, row) = (px - x, py - y);
+            match buffer.get((row * stride + col / 2) as usize) {
+                Some(byte) if col % 2 == 0 => byte >> 4,
+                Some(byte) => byte & 0x0f,
+                None => background,
+            }
+        };
+
+        self.send_resolution(spi)?;
+        self.command(spi, Command::DataStartTransmission1)?;
+        let mut line = [0u8; WIDTH as usize / 2];
+        for py in 0..HEIGHT {
+            for (px, byte) in (0..WIDTH).step_by(2).zip(line.iter_mut()) {
+                *byte = nibble(px, py) << 4 | nibble(px + 1, py);
+            }
+            self.interface.data_all(spi, &line)?;
+        }
+        Ok(())
+    }
+
+    fn display_frame(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::PowerOn)?;
+        self.wait_until_idle(delay, POWER_MS, POWER_TIMEOUT_MS);
+        self.command(spi, Command::DisplayRefresh)?;
+        self.wait_until_idle(delay, REFRESH_MS, REFRESH_TIMEOUT_MS);
+        self.command(spi, Command::PowerOff)?;
+        self.wait_until_idle(delay, POWER_MS, POWER_TIMEOUT_MS);
+        Ok(())
+    }
+
+    fn update_and_display_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        self.update_frame(spi, buffer, delay)?;
+        self.display_frame(spi, delay)
+    }
+
+    fn clear_frame(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        let color_value = OctColor::colors_byte(self.color, self.color);
+
+        self.send_resolution(spi)?;
+        self.command(spi, Command::DataStartTransmission1)?;
+        self.interface.data_x_times(spi, color_value, WIDTH * HEIGHT / 2)?;
+        self.display_frame(spi, delay)
+    }
+
+    fn set_background_color(&mut self, color: OctColor) {
+        self.color = color;
+    }
+
+    fn background_color(&self) -> &OctColor {
+        &self.color
+    }
+
+    fn width(&self) -> u32 {
+        WIDTH
+    }
+
+    fn height(&self) -> u32 {
+        HEIGHT
+    }
+}
+
+impl<SPI, CS, DC, RST, DELAY> Epd5in65f<SPI, CS, NoBusy, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    /// Creates the driver without a BUSY pin, see [Epd5in65f::with_busy]
+    pub fn new(spi: &mut SPI, cs: CS, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
+        <Self as WaveshareDisplay<SPI, CS, DC, RST, DELAY>>::new(spi, cs, dc, rst, delay)
+    }
+}
+
+impl<SPI, CS, BUSY, DC, RST, DELAY> Epd5in65f<SPI, CS, BUSY, DC, RST, DELAY>
+    where
+        SPI: Write<u8>,
+        CS: OutputPin,
+        BUSY: InputPin,
+        DC: OutputPin,
+        RST: OutputPin,
+        DELAY: DelayMs<u8>,
+{
+    /// Creates the driver with the BUSY pin of the panel, which is low while it refreshes
+    pub fn with_busy(
+        spi: &mut SPI,
+        cs: CS,
+        busy: BUSY,
+        dc: DC,
+        rst: RST,
+        delay: &mut DELAY,
+    ) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(cs, dc, rst);
+        let mut epd = Epd5in65f { interface, busy: Some(busy), color: DEFAULT_BACKGROUND_COLOR };
+
+        epd.init(spi, delay)?;
+
+        Ok(epd)
+    }
+
+    /// Puts the controller into deep sleep, the image stays visible
+    ///
+    /// A reset is needed to wake it up, call `wake_up`.
+    pub fn sleep(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        delay.delay_ms(100);
+        self.cmd_with_data(spi, Command::DeepSleep, &[0xA5])
+    }
+
+    /// Resets and initialises the panel after [Epd5in65f::sleep]
+    pub fn wake_up(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.init(spi, delay)
+    }
+
+    /// Waits until the BUSY pin goes high for at most `timeout_ms`, or `typical_ms` without a BUSY pin
+    fn wait_until_idle(&mut self, delay: &mut DELAY, typical_ms: u32, timeout_ms: u32) {
+        let busy = match &self.busy {
+            Some(busy) => busy,
+            None => {
+                for _ in 0..typical_ms / 10 {
+                    delay.delay_ms(10);
+                }
+                return;
+            }
+        };
+        let mut waited = 0;
+        while waited < timeout_ms && busy.is_low().unwrap_or(false) {
+            delay.delay_ms(10);
+            waited += 10;
+        }
+    }
+
+    fn command(&mut self, spi: &mut SPI, command: Command) -> Result<(), SPI::Error> {
+        self.interface.cmd(spi, command)
+    }
+
+    fn cmd_with_data(
+        &mut self,
+        spi: &mut SPI,
+        command: Command,
+        data: &[u8],
+    ) -> Result<(), SPI::Error> {
+        self.interface.cmd_with_data(spi, command, data)
+    }
+
+    fn send_resolution(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.cmd_with_data(
+            spi,
+            Command::ResolutionSetting,
+            &[(WIDTH >> 8) as u8, WIDTH as u8, (HEIGHT >> 8) as u8, HEIGHT as u8],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    /// Records the data bytes, DC high
+    struct Spi {
+        dc: Rc<Cell<bool>>,
+        data: Vec<u8>,
+    }
+
+    impl Write<u8> for Spi {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            if self.dc.get() {
+                self.data.extend_from_slice(words);
+            }
+            Ok(())
+        }
+    }
+
+    struct Pin(Option<Rc<Cell<bool>>>);
+
+    impl OutputPin for Pin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            if let Some(level) = &self.0 {
+                level.set(false);
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            if let Some(level) = &self.0 {
+                level.set(true);
+            }
+            Ok(())
+        }
+    }
+
+    /// Sums up the waited milliseconds
+    struct Delay(u32);
+
+    impl DelayMs<u8> for Delay {
+        fn delay_ms(&mut self, ms: u8) {
+            self.0 += ms as u32;
+        }
+    }
+
+    fn epd() -> (Spi, Delay, Epd5in65f<Spi, Pin, NoBusy, Pin, Pin, Delay>) {
+        let dc = Rc::new(Cell::new(false));
+        let mut spi = Spi { dc: dc.clone(), data: Vec::new() };
+        let mut delay = Delay(0);
+        let epd = Epd5in65f::new(&mut spi, Pin(None), Pin(Some(dc)), Pin(None), &mut delay).unwrap();
+        spi.data.clear();
+        delay.0 = 0;
+        (spi, delay, epd)
+    }
+
+    #[test]
+    fn refresh_without_busy_waits_typical_time() {
+        let (mut spi, mut delay, mut epd) = epd();
+        epd.display_frame(&mut spi, &mut delay).unwrap();
+        assert_eq!(delay.0, REFRESH_MS + 2 * POWER_MS);
+    }
+
+    #[test]
+    fn partial_frame_is_framed_by_background() {
+        let (mut spi, _, mut epd) = epd();
+        epd.set_background_color(OctColor::Blue);
+        // 3x2 window at odd x, rows start on a byte
+        let window = [0x02, 0x30, 0x45, 0x60];
+        epd.update_partial_frame(&mut spi, &window, 1, 1, 3, 2).unwrap();
+
+        let frame = &spi.data[4..];
+        assert_eq!(frame.len(), (WIDTH * HEIGHT / 2) as usize);
+        let row = |y: usize| &frame[y * WIDTH as usize / 2..][..3];
+        assert_eq!(row(0), &[0x33, 0x33, 0x33]);
+        assert_eq!(row(1), &[0x30, 0x23, 0x33]);
+        assert_eq!(row(2), &[0x34, 0x56, 0x33]);
+        assert_eq!(row(3), &[0x33, 0x33, 0x33]);
+    }
+}
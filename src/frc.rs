@@ -0,0 +1,125 @@
+//! Software frame rate control: more shades than the 4 gray levels of the controllers
+//!
+//! The image is drawn into a 4bpp [VarFourBitGrayDisplay]. A flush task calls [Frc::render]
+//! for every frame it sends, each pixel shows the two physical levels around its shade in turns,
+//! so that the eye sees the average.
+//!
+//! ```rust,ignore
+//! let mut buffer = [0u8; 240 * 96 / 2];
+//! let mut source = VarFourBitGrayDisplay::new(240, 96, &mut buffer);
+//! let mut frc = Frc::new(5, FrcPhase::Diagonal);
+//! loop {
+//!     frc.render(&source, &mut display)?;
+//!     lcd.update_frame(&mut spi, display.buffer(), &mut delay)?;
+//!     frc.next_frame();
+//! }
+//! ```
+
+use embedded_graphics::primitives::PointsIter;
+use embedded_graphics_core::image::GetPixel;
+use embedded_graphics_core::prelude::*;
+
+use crate::color::{FourBitGray, TwoBitColor};
+use crate::graphics::FourBitGrayDisplay;
+#[cfg(doc)]
+use crate::graphics::VarFourBitGrayDisplay;
+
+/// Spatial offset of the frame sequence, neighbouring pixels in different phases flicker less
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrcPhase {
+    /// All pixels switch at the same time
+    Uniform,
+    /// Neighbouring pixels are half a cycle apart
+    Checker,
+    /// The phase moves along diagonals, `(x + 2 * y) % frames`
+    Diagonal,
+}
+
+/// Generates the 2bpp frames of a 4bpp image
+pub struct Frc {
+    frames: u8,
+    phase: FrcPhase,
+    frame: u8,
+}
+
+impl Frc {
+    /// `frames` is the length of the cycle, clamped to 1-5
+    ///
+    /// A cycle of `frames` shows `3 * frames + 1` shades, 5 frames show all 16 shades
+    /// of the source, 2 frames give 7 shades with less flicker.
+    pub fn new(frames: u8, phase: FrcPhase) -> Self {
+        Frc {
+            frames: frames.clamp(1, 5),
+            phase,
+            frame: 0,
+        }
+    }
+
+    /// Changes the spatial phase offset
+    pub fn set_phase(&mut self, phase: FrcPhase) {
+        self.phase = phase;
+    }
+
+    /// Moves on to the next frame of the cycle
+    pub fn next_frame(&mut self) {
+        self.frame = (self.frame + 1) % self.frames;
+    }
+
+    /// Physical color of a shade at `point` in the current frame
+    fn color(&self, point: Point, shade: FourBitGray) -> TwoBitColor {
+        let frames = u16::from(self.frames);
+        // number of frame steps above black, 3 levels with `frames` steps each
+        let steps = (u16::from(shade.level()) * 3 * frames + 7) / 15;
+        let (level, extra) = (steps / frames, steps % frames);
+
+        let (x, y) = (point.x.unsigned_abs(), point.y.unsigned_abs());
+        let offset = match self.phase {
+            FrcPhase::Uniform => 0,
+            FrcPhase::Checker => ((x + y) % 2) * (u32::from(self.frames) / 2),
+            FrcPhase::Diagonal => x + 2 * y,
+        };
+        let phase = (u32::from(self.frame) + offset) % u32::from(self.frames);
+        let level = level + u16::from(phase < u32::from(extra));
+        TwoBitColor::from_level(level as u8)
+    }
+
+    /// Draws the current frame of `source`, usually a [VarFourBitGrayDisplay], into `target`
+    pub fn render<S, D>(&self, source: &S, target: &mut D) -> Result<(), D::Error>
+    where
+        S: FourBitGrayDisplay + GetPixel<Color = FourBitGray>,
+        D: DrawTarget<Color = TwoBitColor>,
+    {
+        let area = source.bounding_box();
+        let colors = area.points().map(|point| {
+            let shade = source.pixel(point).unwrap_or(FourBitGray::BLACK);
+            self.color(point, shade)
+        });
+        target.fill_contiguous(&area, colors)
+    }
+}
+
+#[cfg(all(test, feature = "uc1638"))]
+mod tests {
+    use super::*;
+    use crate::graphics::VarFourBitGrayDisplay;
+    use crate::uc1638::prelude::Display2in7;
+
+    #[test]
+    fn cycle_averages_to_shade() {
+        let mut buffer = [0u8; 4 * 4 / 2];
+        let mut source = VarFourBitGrayDisplay::new(4, 4, &mut buffer);
+        let _ = Pixel(Point::new(1, 2), FourBitGray::new(7)).draw(&mut source);
+        assert_eq!(source.pixel(Point::new(1, 2)), Some(FourBitGray::new(7)));
+
+        let mut display = Display2in7::default();
+        let mut frc = Frc::new(5, FrcPhase::Diagonal);
+        let mut levels = 0;
+        for _ in 0..5 {
+            frc.render(&source, &mut display).unwrap();
+            levels += display.pixel(Point::new(1, 2)).unwrap().level();
+            frc.next_frame();
+        }
+        // shade 7 of 15 is 7 steps of 1/5 level above black
+        assert_eq!(levels, 7);
+    }
+}
@@ -1,9 +1,13 @@
 //! Graphics Support for EPDs
 
 use crate::buffer_len;
-use crate::color::{Color, OctColor, TriColor, TwoBitColor};
+use crate::color::{Color, FourBitGray, GrayMap, OctColor, TriColor, TwoBitColor};
+#[cfg(any(feature = "uc1638", feature = "st7571"))]
+use embedded_graphics_core::image::GetPixel;
 use embedded_graphics_core::pixelcolor::BinaryColor;
 use embedded_graphics_core::prelude::*;
+#[cfg(any(feature = "uc1638", feature = "st7571"))]
+use embedded_graphics_core::primitives::Rectangle;
 
 /// Displayrotation
 #[derive(Clone, Copy)]
@@ -24,6 +28,43 @@ impl Default for DisplayRotation {
     }
 }
 
+/// Raster operation used when a pixel is written into a framebuffer
+///
+/// The operation works on the packed bits of the buffer, a set bit always meaning
+/// "ink" (`BinaryColor::On` or the darker half of a `TwoBitColor` code).
+/// XOR-ing the same shape twice restores the previous content, which is handy for
+/// blinking cursors and selection highlights.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RasterOp {
+    /// Overwrite the destination (normal drawing)
+    #[default]
+    Copy,
+    /// Keep ink only where both destination and source have it
+    And,
+    /// Add the ink of the source to the destination
+    Or,
+    /// Toggle the destination where the source has ink
+    Xor,
+    /// Invert the destination, the color of the source is ignored
+    Invert,
+}
+
+impl RasterOp {
+    /// Combines the bits of `dst` selected by `mask` with the same bits of `src`
+    ///
+    /// Bits outside of `mask` are returned unchanged.
+    pub fn apply(self, dst: u8, src: u8, mask: u8) -> u8 {
+        let value = match self {
+            RasterOp::Copy => src,
+            RasterOp::And => dst & src,
+            RasterOp::Or => dst | src,
+            RasterOp::Xor => dst ^ src,
+            RasterOp::Invert => !dst,
+        };
+        (dst & !mask) | (value & mask)
+    }
+}
+
 /// Necessary traits for all displays to implement for drawing
 ///
 /// Adds support for:
@@ -57,6 +98,16 @@ pub trait Display: DrawTarget<Color = BinaryColor> {
     /// Get the current rotation of the display
     fn rotation(&self) -> DisplayRotation;
 
+    /// Sets the raster operation used for all following drawing
+    ///
+    /// Ignored by displays which only support [`RasterOp::Copy`]
+    fn set_raster_op(&mut self, _op: RasterOp) {}
+
+    /// Get the current raster operation of the display
+    fn raster_op(&self) -> RasterOp {
+        RasterOp::Copy
+    }
+
     /// Helperfunction for the Embedded Graphics draw trait
     ///
     /// Becomes uneccesary when const_generics become stablised
@@ -67,6 +118,7 @@ pub trait Display: DrawTarget<Color = BinaryColor> {
         pixel: Pixel<BinaryColor>,
     ) -> Result<(), Self::Error> {
         let rotation = self.rotation();
+        let op = self.raster_op();
         let buffer = self.get_mut_buffer();
 
         let Pixel(point, color) = pixel;
@@ -78,17 +130,12 @@ pub trait Display: DrawTarget<Color = BinaryColor> {
         let (index, bit) = find_position(point.x as u32, point.y as u32, width, height, rotation);
         let index = index as usize;
 
-        // "Draw" the Pixel on that bit
-        match color {
-            // Black
-            BinaryColor::On => {
-                buffer[index] &= !bit;
-            }
-            // White
-            BinaryColor::Off => {
-                buffer[index] |= bit;
-            }
-        }
+        // "Draw" the Pixel on that bit, a cleared bit is black here so the ink is inverted
+        let ink = match color {
+            BinaryColor::On => 0xff,
+            BinaryColor::Off => 0x00,
+        };
+        buffer[index] = !op.apply(!buffer[index], ink, bit);
         Ok(())
     }
 }
@@ -238,6 +285,33 @@ pub trait OctDisplay: DrawTarget<Color = OctColor> {
 
 
 
+/// Encoding of the `TwoBitColor` shades in the buffer of a controller
+///
+/// Implemented by each framebuffer layout, so that filling a buffer or the lcd RAM with
+/// a gray really produces that gray on the panel.
+pub trait GrayEncoding {
+    /// Number of bytes holding one column of a page
+    const COLUMN_BYTES: usize;
+
+    /// 2 bit code of a pixel
+    fn code(color: TwoBitColor) -> u8;
+
+    /// Color of a 2 bit code
+    fn decode(code: u8) -> TwoBitColor;
+
+    /// Bytes of one column of a page filled with `color`,
+    /// only the first [GrayEncoding::COLUMN_BYTES] are used
+    fn fill_pattern(color: TwoBitColor) -> [u8; 2];
+
+    /// Fills a whole buffer with `color`
+    fn fill(buffer: &mut [u8], color: TwoBitColor) {
+        let pattern = Self::fill_pattern(color);
+        for column in buffer.chunks_mut(Self::COLUMN_BYTES) {
+            column.copy_from_slice(&pattern[..column.len()]);
+        }
+    }
+}
+
 ///定义一个 trait 继承自DrawTarget
 /// 实现此trait 后可以被用于 实现了 Drawable 类型的 draw 方法中
 pub trait TwoBitColorDisplay: DrawTarget<Color = TwoBitColor> {
@@ -260,6 +334,26 @@ pub trait TwoBitColorDisplay: DrawTarget<Color = TwoBitColor> {
     /// Get the current rotation of the display
     fn rotation(&self) -> DisplayRotation;
 
+    /// Sets the table from logical grays to the shades drawn on the panel
+    ///
+    /// Ignored by displays which always draw [`GrayMap::IDENTITY`]
+    fn set_gray_map(&mut self, _map: GrayMap) {}
+
+    /// Get the current gray table of the display
+    fn gray_map(&self) -> GrayMap {
+        GrayMap::IDENTITY
+    }
+
+    /// Sets the raster operation used for all following drawing
+    ///
+    /// Ignored by displays which only support [`RasterOp::Copy`]
+    fn set_raster_op(&mut self, _op: RasterOp) {}
+
+    /// Get the current raster operation of the display
+    fn raster_op(&self) -> RasterOp {
+        RasterOp::Copy
+    }
+
     /// Helperfunction for the Embedded Graphics draw trait
     ///
     /// Becomes uneccesary when const_generics become stablised
@@ -270,6 +364,8 @@ pub trait TwoBitColorDisplay: DrawTarget<Color = TwoBitColor> {
         pixel: Pixel<TwoBitColor>,
     ) -> Result<(), Self::Error> {
         let rotation = self.rotation();
+        let op = self.raster_op();
+        let map = self.gray_map();
         let buffer = self.get_mut_buffer();
 
         let Pixel(point, color) = pixel;
@@ -283,28 +379,76 @@ pub trait TwoBitColorDisplay: DrawTarget<Color = TwoBitColor> {
             find_gray2_position(point.x as u32, point.y as u32, width, height, rotation);
         let index = index as usize;
 
-        // "Draw" the Pixel on that bit
-        match color {
-            TwoBitColor::Black => {
-                // clear bit in bw-buffer -> black
-                buffer[index] &= !bit;
-            }
-            TwoBitColor::White => {
-                // set bit in bw-buffer -> white
-                buffer[index] |= bit;
-            },
-            TwoBitColor::Gray1 => {
-                let code:u8 =0x55  & bit;
-                buffer[index] &= !bit;//先置为零
-                buffer[index] |= code;
-            },
-            TwoBitColor::Gray2 =>{
-                let code:u8 = 0xAA & bit;
-                buffer[index] &= !bit;//先置为零
-                buffer[index] |= code;
-            }
+        // "Draw" the Pixel on that bit, 2 bits per pixel: 00 white, 01 gray1, 10 gray2, 11 black
+        let ink = match map.map(color) {
+            TwoBitColor::Black => 0xFF,
+            TwoBitColor::White => 0x00,
+            TwoBitColor::Gray1 => 0x55,
+            TwoBitColor::Gray2 => 0xAA,
+        };
+        buffer[index] = op.apply(buffer[index], ink, bit);
+
+        Ok(())
+    }
+}
+
+/// Necessary traits for all 16 gray displays to implement for drawing
+///
+/// Adds support for:
+/// - Drawing (With the help of DrawTarget/Embedded Graphics)
+/// - Rotations
+/// - Clearing
+pub trait FourBitGrayDisplay: DrawTarget<Color = FourBitGray> {
+    /// Clears the buffer of the display with the chosen background color
+    fn clear_buffer(&mut self, background_color: FourBitGray) {
+        let level = background_color.level();
+        self.get_mut_buffer().fill(level << 4 | level);
+    }
+
+    /// Returns the buffer
+    fn buffer(&self) -> &[u8];
+
+    /// Returns a mutable buffer
+    fn get_mut_buffer(&mut self) -> &mut [u8];
+
+    /// Sets the rotation of the display
+    fn set_rotation(&mut self, rotation: DisplayRotation);
+
+    /// Get the current rotation of the display
+    fn rotation(&self) -> DisplayRotation;
+
+    /// Sets the raster operation used for all following drawing
+    fn set_raster_op(&mut self, op: RasterOp);
+
+    /// Get the current raster operation of the display
+    fn raster_op(&self) -> RasterOp;
+
+    /// Helperfunction for the Embedded Graphics draw trait
+    ///
+    /// Becomes uneccesary when const_generics become stablised
+    fn draw_helper(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel: Pixel<FourBitGray>,
+    ) -> Result<(), Self::Error> {
+        let rotation = self.rotation();
+        let op = self.raster_op();
+        let buffer = self.get_mut_buffer();
+
+        let Pixel(point, color) = pixel;
+        if outside_display(point, width, height, rotation) {
+            return Ok(());
         }
 
+        // Give us index inside the buffer and whether the pixel is the high nibble
+        let (index, high) =
+            find_gray4_position(point.x as u32, point.y as u32, width, height, rotation);
+        let index = index as usize;
+
+        let level = color.level();
+        let mask = if high { 0xf0 } else { 0x0f };
+        buffer[index] = op.apply(buffer[index], level << 4 | level, mask);
         Ok(())
     }
 }
@@ -339,6 +483,7 @@ pub struct VarDisplay<'a> {
     width: u32,
     height: u32,
     rotation: DisplayRotation,
+    raster_op: RasterOp,
     buffer: &'a mut [u8], //buffer: Box<u8>//[u8; 15000]
 }
 
@@ -353,6 +498,7 @@ impl<'a> VarDisplay<'a> {
             width,
             height,
             rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
             buffer,
         }
     }
@@ -395,6 +541,224 @@ impl<'a> Display for VarDisplay<'a> {
     fn rotation(&self) -> DisplayRotation {
         self.rotation
     }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
+}
+
+/// A variable 16 gray display without a predefined buffer
+///
+/// 2 pixels per byte, the left pixel in the high nibble, rows of (width + 1) / 2 bytes.
+/// This is the RAM layout of 4bpp controllers like the SSD1322.
+pub struct VarFourBitGrayDisplay<'a> {
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    raster_op: RasterOp,
+    buffer: &'a mut [u8],
+}
+
+impl<'a> VarFourBitGrayDisplay<'a> {
+    /// Create a new variable sized display.
+    ///
+    /// Buffersize must be at least (width + 1) / 2 * height bytes.
+    pub fn new(width: u32, height: u32, buffer: &'a mut [u8]) -> VarFourBitGrayDisplay<'a> {
+        assert!(buffer.len() >= (width.div_ceil(2) * height) as usize);
+        VarFourBitGrayDisplay {
+            width,
+            height,
+            rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
+            buffer,
+        }
+    }
+}
+
+impl<'a> DrawTarget for VarFourBitGrayDisplay<'a> {
+    type Color = FourBitGray;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            self.draw_helper(self.width, self.height, pixel)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> OriginDimensions for VarFourBitGrayDisplay<'a> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl<'a> embedded_graphics_core::image::GetPixel for VarFourBitGrayDisplay<'a> {
+    type Color = FourBitGray;
+
+    fn pixel(&self, point: Point) -> Option<FourBitGray> {
+        if outside_display(point, self.width, self.height, self.rotation) {
+            return None;
+        }
+        let (index, high) =
+            find_gray4_position(point.x as u32, point.y as u32, self.width, self.height, self.rotation);
+        let byte = self.buffer[index as usize];
+        Some(FourBitGray::new(if high { byte >> 4 } else { byte }))
+    }
+}
+
+impl<'a> FourBitGrayDisplay for VarFourBitGrayDisplay<'a> {
+    fn buffer(&self) -> &[u8] {
+        self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
+}
+
+/// Geometry of a framebuffer made of vertical pages
+///
+/// Every column of a page of `rows` lines is stored in `bytes` consecutive bytes,
+/// the columns of a page follow each other.
+#[cfg(any(feature = "uc1638", feature = "st7571"))]
+#[derive(Clone, Copy)]
+pub(crate) struct PageLayout {
+    pub width: u32,
+    pub rows: u32,
+    pub bytes: u32,
+}
+
+#[cfg(any(feature = "uc1638", feature = "st7571"))]
+impl PageLayout {
+    /// Moves whole pages from `src` to `dst`, both given in unrotated coordinates
+    ///
+    /// Returns `false` without touching the buffer if the rows are not page aligned.
+    fn copy_pages(&self, buffer: &mut [u8], src: Rectangle, dst: Point) -> bool {
+        let rows = self.rows as i32;
+        if src.top_left.y % rows != 0 || dst.y % rows != 0 || src.size.height as i32 % rows != 0 {
+            return false;
+        }
+        let page_len = (self.width * self.bytes) as usize;
+        let len = (src.size.width * self.bytes) as usize;
+        let src_x = src.top_left.x as usize * self.bytes as usize;
+        let dst_x = dst.x as usize * self.bytes as usize;
+        let src_page = (src.top_left.y / rows) as usize;
+        let dst_page = (dst.y / rows) as usize;
+
+        let pages = (src.size.height / self.rows) as usize;
+        for i in 0..pages {
+            // walk backwards when moving down so that overlapping pages are read before written
+            let i = if dst_page > src_page { pages - 1 - i } else { i };
+            let from = (src_page + i) * page_len + src_x;
+            let to = (dst_page + i) * page_len + dst_x;
+            buffer.copy_within(from..from + len, to);
+        }
+        true
+    }
+}
+
+/// Copies the pixels of `src` so that its top left corner ends up at `dst`
+///
+/// Both areas are clipped to the display, overlapping areas are handled. Whole bytes
+/// are moved when the display is not rotated and the rows are page aligned, otherwise
+/// the pixels are moved one by one. The raster operation of the display is not used.
+#[cfg(any(feature = "uc1638", feature = "st7571"))]
+pub(crate) fn copy_region<D>(display: &mut D, layout: PageLayout, src: Rectangle, dst: Point)
+where
+    D: TwoBitColorDisplay + GetPixel<Color = TwoBitColor>,
+{
+    let bounds = display.bounding_box();
+    let clipped = src.intersection(&bounds);
+    let dst = dst + (clipped.top_left - src.top_left);
+    let moved = Rectangle::new(dst, clipped.size).intersection(&bounds);
+    let src = Rectangle::new(clipped.top_left + (moved.top_left - dst), moved.size);
+    let dst = moved.top_left;
+    if src.is_zero_sized() || src.top_left == dst {
+        return;
+    }
+
+    if let DisplayRotation::Rotate0 = display.rotation() {
+        if layout.copy_pages(display.get_mut_buffer(), src, dst) {
+            return;
+        }
+    }
+
+    let op = display.raster_op();
+    display.set_raster_op(RasterOp::Copy);
+    let (width, height) = (src.size.width as i32, src.size.height as i32);
+    // read the pixels before they are overwritten by walking away from the destination
+    let backwards = (dst.y, dst.x) > (src.top_left.y, src.top_left.x);
+    for row in 0..height {
+        for col in 0..width {
+            let offset = if backwards {
+                Point::new(width - 1 - col, height - 1 - row)
+            } else {
+                Point::new(col, row)
+            };
+            if let Some(color) = display.pixel(src.top_left + offset) {
+                let _ = display.draw_iter(core::iter::once(Pixel(dst + offset, color)));
+            }
+        }
+    }
+    display.set_raster_op(op);
+}
+
+/// Moves the content of `area` by `dx`, `dy` and fills the uncovered part with `fill`
+#[cfg(any(feature = "uc1638", feature = "st7571"))]
+pub(crate) fn scroll_region<D>(
+    display: &mut D,
+    layout: PageLayout,
+    area: Rectangle,
+    dx: i32,
+    dy: i32,
+    fill: TwoBitColor,
+) where
+    D: TwoBitColorDisplay + GetPixel<Color = TwoBitColor>,
+{
+    let area = area.intersection(&display.bounding_box());
+    let delta = Point::new(dx, dy);
+    let src = area.intersection(&Rectangle::new(area.top_left - delta, area.size));
+    copy_region(display, layout, src, src.top_left + delta);
+
+    let op = display.raster_op();
+    display.set_raster_op(RasterOp::Copy);
+    let (width, height) = (area.size.width as i32, area.size.height as i32);
+    let w = dx.unsigned_abs().min(area.size.width);
+    let h = dy.unsigned_abs().min(area.size.height);
+    let (x, y) = (area.top_left.x, area.top_left.y);
+    // rows which were uncovered at the top or bottom
+    let rows_y = if dy > 0 { y } else { y + height - h as i32 };
+    let rows = Rectangle::new(Point::new(x, rows_y), Size::new(area.size.width, h));
+    let _ = display.fill_solid(&rows, fill);
+    // columns which were uncovered at the left or right
+    let cols_x = if dx > 0 { x } else { x + width - w as i32 };
+    let cols = Rectangle::new(Point::new(cols_x, y), Size::new(w, area.size.height));
+    let _ = display.fill_solid(&cols, fill);
+    display.set_raster_op(op);
 }
 
 /// Checks if a pos is outside the defined display
@@ -464,6 +828,11 @@ fn find_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotat
     )
 }
 
+fn find_gray4_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotation) -> (u32, bool) {
+    let (nx, ny) = find_rotation(x, y, width, height, rotation);
+    (nx / 2 + width.div_ceil(2) * ny, nx % 2 == 0)
+}
+
 #[rustfmt::skip]
 fn find_gray2_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotation) -> (u32, u8) {
     let (nx, ny) = find_rotation(x, y, width, height, rotation);
@@ -476,11 +845,59 @@ fn find_gray2_position(x: u32, y: u32, width: u32, height: u32, rotation: Displa
 
 #[cfg(test)]
 mod tests {
-    use super::{buffer_len, find_position, outside_display, Display, DisplayRotation, VarDisplay};
-    use crate::color::Black;
-    use crate::color::Color;
+    use super::{buffer_len, Display, FourBitGrayDisplay, RasterOp, VarDisplay, VarFourBitGrayDisplay};
+    use crate::color::{Black, FourBitGray};
+    use embedded_graphics::image::GetPixel;
     use embedded_graphics::{
         prelude::*,
         primitives::{Line, PrimitiveStyle},
     };
+
+    #[test]
+    fn raster_op_only_touches_mask() {
+        assert_eq!(RasterOp::Copy.apply(0b1010_1010, 0xff, 0b0000_1111), 0b1010_1111);
+        assert_eq!(RasterOp::And.apply(0b1010_1010, 0x00, 0b0000_1111), 0b1010_0000);
+        assert_eq!(RasterOp::Or.apply(0b1010_1010, 0xff, 0b0000_0001), 0b1010_1011);
+        assert_eq!(RasterOp::Xor.apply(0b1010_1010, 0xff, 0b0000_1111), 0b1010_0101);
+        assert_eq!(RasterOp::Invert.apply(0b1010_1010, 0x00, 0b1111_0000), 0b0101_1010);
+    }
+
+    #[test]
+    fn xor_twice_restores_buffer() {
+        let mut buffer = [0xffu8; buffer_len(16, 4)];
+        let mut display = VarDisplay::new(16, 4, &mut buffer);
+        let _ = Line::new(Point::new(0, 1), Point::new(7, 1))
+            .into_styled(PrimitiveStyle::with_stroke(Black, 1))
+            .draw(&mut display);
+        let before = [display.buffer()[0], display.buffer()[2], display.buffer()[4]];
+
+        display.set_raster_op(RasterOp::Xor);
+        let cursor = Line::new(Point::new(4, 0), Point::new(4, 3))
+            .into_styled(PrimitiveStyle::with_stroke(Black, 1));
+        let _ = cursor.draw(&mut display);
+        assert_eq!(display.buffer()[0], 0b1111_0111);
+        assert_eq!(display.buffer()[2], 0b0000_1000);
+
+        let _ = cursor.draw(&mut display);
+        assert_eq!(
+            [display.buffer()[0], display.buffer()[2], display.buffer()[4]],
+            before
+        );
+    }
+
+    #[test]
+    fn four_bit_gray_nibbles() {
+        let mut buffer = [0u8; 3 * 2];
+        let mut display = VarFourBitGrayDisplay::new(5, 2, &mut buffer);
+        display.clear_buffer(FourBitGray::WHITE);
+        let _ = Pixel(Point::new(0, 1), FourBitGray::new(3)).draw(&mut display);
+        let _ = Pixel(Point::new(4, 1), FourBitGray::new(9)).draw(&mut display);
+        assert_eq!(display.buffer()[3..], [0x3f, 0xff, 0x9f]);
+        assert_eq!(display.pixel(Point::new(4, 1)), Some(FourBitGray::new(9)));
+
+        display.set_raster_op(RasterOp::Xor);
+        let _ = Pixel(Point::new(1, 1), FourBitGray::new(0x0a)).draw(&mut display);
+        assert_eq!(display.pixel(Point::new(1, 1)), Some(FourBitGray::new(0x05)));
+    }
+
 }
@@ -150,3 +150,20 @@ where
         delay.delay_ms(250);
     }
 }
+
+/// Stands in for the BUSY pin of drivers created without one, it always reads idle
+#[cfg(any(feature = "epd2in9b", feature = "epd5in65f"))]
+pub struct NoBusy;
+
+#[cfg(any(feature = "epd2in9b", feature = "epd5in65f"))]
+impl InputPin for NoBusy {
+    type Error = core::convert::Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
@@ -4,6 +4,14 @@
 
 
 pub mod graphics;
+pub mod antialias;
+pub mod compositor;
+pub mod dither;
+pub mod frc;
+pub mod palette;
+pub mod snapshot;
+#[cfg(feature = "critical-section")]
+pub mod shared;
 
 #[cfg(not(feature = "async"))]
 mod traits;
@@ -28,6 +36,11 @@ pub mod st7571;
 #[cfg(feature = "sharp1in26")]
 pub mod sharp1in26;
 
+#[cfg(feature = "epd2in9b")]
+pub mod epd2in9b;
+#[cfg(feature = "epd5in65f")]
+pub mod epd5in65f;
+
 /// Includes everything important besides the chosen Display
 pub mod prelude {
     pub use crate::color::{Color, OctColor, TriColor};
@@ -41,18 +54,25 @@ pub mod prelude {
     };
 
 
-    pub use crate::graphics::{Display, DisplayRotation, OctDisplay, TriDisplay};
+    pub use crate::graphics::{Display, DisplayRotation, OctDisplay, RasterOp, TriDisplay};
 
     #[cfg(all(feature = "uc1638",feature = "async"))]
     pub use crate::uc1638::lcd_async::Lcd2in7;
     #[cfg(all(feature = "uc1638",not(feature = "async")))]
     pub use crate::uc1638::lcd_blocking::Lcd2in7;
 
-    #[cfg(feature = "st7571")]
-    pub use crate::st7571::Lcd2in3;
+    #[cfg(all(feature = "st7571", feature = "async"))]
+    pub use crate::st7571::lcd_async::Lcd2in3;
+    #[cfg(all(feature = "st7571", not(feature = "async")))]
+    pub use crate::st7571::lcd_blocking::Lcd2in3;
 
     #[cfg(feature = "sharp1in26")]
     pub use crate::sharp1in26::Lcd1in26;
+
+    #[cfg(feature = "epd2in9b")]
+    pub use crate::epd2in9b::Epd2in9b;
+    #[cfg(feature = "epd5in65f")]
+    pub use crate::epd5in65f::Epd5in65f;
 }
 
 /// Computes the needed buffer length. Takes care of rounding up in case width
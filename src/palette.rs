@@ -0,0 +1,207 @@
+//! Conversion of rgb colors into the few colors of a panel
+//!
+//! A [Palette] is a const table of the panel colors with their rgb values. The color types of
+//! the crate convert `Rgb888` through their palette, e.g. [OctColor::PALETTE], so every panel
+//! maps images the same way. For large images a [PaletteLut] caches the nearest color of
+//! every rgb value with a few bits per channel.
+//!
+//! ```rust,ignore
+//! const SIGN: Palette<TriColor, 3> = Palette::new([
+//!     (TriColor::White, Rgb888::new(0xff, 0xff, 0xff)),
+//!     (TriColor::Black, Rgb888::new(0x20, 0x20, 0x20)),
+//!     (TriColor::Chromatic, Rgb888::new(0xc0, 0x20, 0x20)),
+//! ])
+//! .with_distance(Distance::Weighted);
+//! let lut = SIGN.lut();
+//! let color = lut.nearest(Rgb888::new(200, 10, 10));
+//! ```
+
+use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+
+#[cfg(doc)]
+use crate::color::OctColor;
+
+/// How the distance between two rgb colors is measured
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Distance {
+    /// Squared distance in rgb space
+    Euclidean,
+    /// Channels weighted by the eye's sensitivity ("redmean"), closer to the perceived difference
+    Weighted,
+}
+
+impl Distance {
+    /// Distance between `a` and `b`, only usable for comparisons
+    pub fn between(self, a: Rgb888, b: Rgb888) -> u32 {
+        let dr = i32::from(a.r()) - i32::from(b.r());
+        let dg = i32::from(a.g()) - i32::from(b.g());
+        let db = i32::from(a.b()) - i32::from(b.b());
+        let distance = match self {
+            Distance::Euclidean => dr * dr + dg * dg + db * db,
+            Distance::Weighted => {
+                let mean_r = (i32::from(a.r()) + i32::from(b.r())) / 2;
+                (((512 + mean_r) * dr * dr) >> 8) + 4 * dg * dg + (((767 - mean_r) * db * db) >> 8)
+            }
+        };
+        distance as u32
+    }
+}
+
+/// Colors of a panel with their rgb values
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette<C, const N: usize> {
+    entries: [(C, Rgb888); N],
+    distance: Distance,
+}
+
+impl<C: Copy + PartialEq, const N: usize> Palette<C, N> {
+    /// Creates a palette using the euclidean distance, `N` has to be 1-256
+    pub const fn new(entries: [(C, Rgb888); N]) -> Self {
+        assert!(N > 0 && N <= 256);
+        Palette {
+            entries,
+            distance: Distance::Euclidean,
+        }
+    }
+
+    /// Changes how the nearest color is found
+    pub const fn with_distance(mut self, distance: Distance) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    /// The colors and their rgb values
+    pub fn entries(&self) -> &[(C, Rgb888); N] {
+        &self.entries
+    }
+
+    /// Rgb value of `color`, `None` if it isn't in the palette
+    pub fn rgb(&self, color: C) -> Option<Rgb888> {
+        self.entries
+            .iter()
+            .find(|(c, _)| *c == color)
+            .map(|(_, rgb)| *rgb)
+    }
+
+    /// Index of the entry nearest to `rgb`, the first one wins a tie
+    pub fn nearest_index(&self, rgb: Rgb888) -> usize {
+        let mut best = (0, u32::MAX);
+        for (index, (_, entry)) in self.entries.iter().enumerate() {
+            let distance = self.distance.between(*entry, rgb);
+            if distance < best.1 {
+                best = (index, distance);
+            }
+        }
+        best.0
+    }
+
+    /// Color nearest to `rgb`
+    pub fn nearest(&self, rgb: Rgb888) -> C {
+        self.entries[self.nearest_index(rgb)].0
+    }
+
+    /// Builds a lookup table with 4 bits per channel
+    ///
+    /// The table is 4 KiB and is built on the stack, use [Palette::lut_in] to choose the
+    /// precision and where the table is stored.
+    pub fn lut(&self) -> PaletteLut<'_, C, N> {
+        let mut table = [0; LUT_LEN];
+        self.fill_lut(4, &mut table);
+        PaletteLut {
+            palette: self,
+            bits: 4,
+            table,
+        }
+    }
+
+    /// Builds a lookup table with `bits` (1-8) per channel in `table` of [lut_len] bytes
+    pub fn lut_in<'t>(&self, bits: u32, table: &'t mut [u8]) -> PaletteLut<'_, C, N, &'t mut [u8]> {
+        assert!((1..=8).contains(&bits) && table.len() >= lut_len(bits));
+        self.fill_lut(bits, table);
+        PaletteLut {
+            palette: self,
+            bits,
+            table,
+        }
+    }
+
+    fn fill_lut(&self, bits: u32, table: &mut [u8]) {
+        for (cell, index) in table[..lut_len(bits)].iter_mut().enumerate() {
+            *index = self.nearest_index(lut_rgb(cell, bits)) as u8;
+        }
+    }
+}
+
+const LUT_LEN: usize = lut_len(4);
+
+/// Bytes of a lookup table with `bits` per channel
+pub const fn lut_len(bits: u32) -> usize {
+    1 << (3 * bits)
+}
+
+/// Rgb value at the center of a lookup table cell
+fn lut_rgb(cell: usize, bits: u32) -> Rgb888 {
+    let step = 1 << (8 - bits);
+    let channel = |shift: u32| (((cell >> shift) & ((1 << bits) - 1)) * step + step / 2) as u8;
+    Rgb888::new(channel(2 * bits), channel(bits), channel(0))
+}
+
+fn lut_cell(rgb: Rgb888, bits: u32) -> usize {
+    let channel = |value: u8| usize::from(value >> (8 - bits));
+    channel(rgb.r()) << (2 * bits) | channel(rgb.g()) << bits | channel(rgb.b())
+}
+
+/// Nearest colors of a [Palette] for all rgb values with a few bits per channel
+///
+/// The table is owned by [Palette::lut] and borrowed by [Palette::lut_in].
+pub struct PaletteLut<'a, C, const N: usize, T = [u8; LUT_LEN]> {
+    palette: &'a Palette<C, N>,
+    bits: u32,
+    table: T,
+}
+
+impl<'a, C: Copy, const N: usize, T: AsRef<[u8]>> PaletteLut<'a, C, N, T> {
+    /// Color nearest to `rgb`, the low bits of each channel are ignored
+    pub fn nearest(&self, rgb: Rgb888) -> C {
+        let index = self.table.as_ref()[lut_cell(rgb, self.bits)];
+        self.palette.entries[usize::from(index)].0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{OctColor, TwoBitColor};
+
+    #[test]
+    fn lut_matches_search() {
+        let lut = OctColor::PALETTE.lut();
+        for rgb in [
+            Rgb888::new(0xff, 0x80, 0x00),
+            Rgb888::new(0x10, 0xe0, 0x20),
+            Rgb888::new(0xf0, 0xf0, 0x30),
+            Rgb888::new(0x11, 0x22, 0x33),
+        ] {
+            assert_eq!(lut.nearest(rgb), OctColor::PALETTE.nearest(rgb));
+        }
+        assert_eq!(lut.nearest(Rgb888::new(0xf0, 0x78, 0x08)), OctColor::Orange);
+
+        let mut table = [0; lut_len(3)];
+        let small = OctColor::PALETTE.lut_in(3, &mut table);
+        assert_eq!(small.nearest(Rgb888::new(0x10, 0xe0, 0x20)), OctColor::Green);
+        assert_eq!(lut_rgb(0xfff, 4), Rgb888::new(0xf8, 0xf8, 0xf8));
+        assert_eq!(TwoBitColor::PALETTE.rgb(TwoBitColor::Gray1), Some(Rgb888::new(0xaa, 0xaa, 0xaa)));
+    }
+
+    #[test]
+    fn weighted_prefers_perceived_match() {
+        // in dark colors a difference in blue weighs more than one in red
+        let palette = Palette::new([
+            ('r', Rgb888::new(130, 0, 0)),
+            ('b', Rgb888::new(0, 0, 128)),
+        ]);
+        let black = Rgb888::new(0, 0, 0);
+        assert_eq!(palette.nearest(black), 'b');
+        assert_eq!(palette.with_distance(Distance::Weighted).nearest(black), 'r');
+    }
+}
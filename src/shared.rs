@@ -0,0 +1,135 @@
+//! Framebuffer shared between interrupt handlers and the main loop
+//!
+//! Every access runs inside a `critical_section`, so a widget drawn in one
+//! [SharedDisplay::draw] call is never sent half finished.
+//!
+//! ```rust,ignore
+//! static DISPLAY: SharedDisplay<Display2in7> = SharedDisplay::new();
+//!
+//! // main
+//! DISPLAY.init(Display2in7::default());
+//!
+//! // interrupt handler
+//! DISPLAY.draw(bar, |display| bar_graph.draw(display));
+//!
+//! // main loop, copy the buffer out so interrupts are only blocked for the copy
+//! if let Some(Some(_area)) = DISPLAY.flush(|display, area| {
+//!     buffer.copy_from_slice(display.buffer());
+//!     area
+//! }) {
+//!     lcd.update_frame(&mut spi, &buffer, &mut delay)?;
+//! }
+//! ```
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use embedded_graphics_core::geometry::Dimensions;
+use embedded_graphics_core::primitives::Rectangle;
+
+use crate::compositor::union;
+
+struct Shared<D> {
+    display: D,
+    /// Area which changed since the last flush
+    dirty: Option<Rectangle>,
+}
+
+/// A framebuffer which can be placed in a `static` and used from interrupts
+pub struct SharedDisplay<D> {
+    inner: Mutex<RefCell<Option<Shared<D>>>>,
+}
+
+impl<D> Default for SharedDisplay<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> SharedDisplay<D> {
+    /// Creates an empty handle, [SharedDisplay::init] has to be called before drawing
+    pub const fn new() -> Self {
+        SharedDisplay {
+            inner: Mutex::new(RefCell::new(None)),
+        }
+    }
+}
+
+impl<D: Dimensions> SharedDisplay<D> {
+    /// Moves the framebuffer into the handle, the whole display is marked as dirty
+    pub fn init(&self, display: D) {
+        let dirty = Some(display.bounding_box());
+        critical_section::with(|cs| {
+            self.inner.borrow_ref_mut(cs).replace(Shared { display, dirty });
+        });
+    }
+
+    /// Draws with exclusive access to the framebuffer and marks `area` as dirty
+    ///
+    /// Interrupts are disabled while `f` runs, keep it short.
+    /// Returns `None` if the handle was not initialised.
+    pub fn draw<R>(&self, area: Rectangle, f: impl FnOnce(&mut D) -> R) -> Option<R> {
+        critical_section::with(|cs| {
+            let mut inner = self.inner.borrow_ref_mut(cs);
+            let shared = inner.as_mut()?;
+            let result = f(&mut shared.display);
+            let area = area.intersection(&shared.display.bounding_box());
+            shared.dirty = union(shared.dirty, Some(area));
+            Some(result)
+        })
+    }
+
+    /// Marks the whole framebuffer as dirty
+    pub fn invalidate(&self) {
+        critical_section::with(|cs| {
+            if let Some(shared) = self.inner.borrow_ref_mut(cs).as_mut() {
+                shared.dirty = Some(shared.display.bounding_box());
+            }
+        });
+    }
+
+    /// Area which changed since the last flush
+    pub fn dirty_area(&self) -> Option<Rectangle> {
+        critical_section::with(|cs| self.inner.borrow_ref(cs).as_ref()?.dirty)
+    }
+
+    /// Hands the framebuffer and its dirty area to `f` and resets the dirty area
+    ///
+    /// Nothing can draw while `f` runs, so the frame can't tear. As interrupts are
+    /// disabled, `f` should rather copy the buffer than send it to the lcd.
+    /// Returns `None` if the handle was not initialised.
+    pub fn flush<R>(&self, f: impl FnOnce(&D, Option<Rectangle>) -> R) -> Option<R> {
+        critical_section::with(|cs| {
+            let mut inner = self.inner.borrow_ref_mut(cs);
+            let shared = inner.as_mut()?;
+            let area = shared.dirty.take();
+            Some(f(&shared.display, area))
+        })
+    }
+}
+
+#[cfg(all(test, feature = "uc1638"))]
+mod tests {
+    use super::*;
+    use crate::color::TwoBitColor;
+    use crate::graphics::TwoBitColorDisplay;
+    use crate::uc1638::prelude::Display2in7;
+    use embedded_graphics::prelude::*;
+
+    static DISPLAY: SharedDisplay<Display2in7> = SharedDisplay::new();
+
+    #[test]
+    fn draw_marks_dirty_until_flush() {
+        assert_eq!(DISPLAY.draw(Rectangle::zero(), |_| ()), None);
+        DISPLAY.init(Display2in7::default());
+        let _ = DISPLAY.flush(|_, _| ());
+        assert_eq!(DISPLAY.dirty_area(), None);
+
+        let area = Rectangle::new(Point::new(2, 3), Size::new(1, 1));
+        let pixel = Pixel(area.top_left, TwoBitColor::Black);
+        let _ = DISPLAY.draw(area, |display| pixel.draw(display));
+        let first = DISPLAY.flush(|display, dirty| (display.buffer()[2], dirty));
+        assert_eq!(first, Some((0b1100_0000, Some(area))));
+        assert_eq!(DISPLAY.dirty_area(), None);
+    }
+}
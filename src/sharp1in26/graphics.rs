@@ -2,15 +2,19 @@ use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::geometry::Size;
 use embedded_graphics::Pixel;
 use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::geometry::Point;
+use embedded_graphics::image::GetPixel;
+use crate::snapshot::{BufferLayout, Snapshot};
 use embedded_graphics_core::pixelcolor::BinaryColor;
 use crate::color::Color;
 use crate::sharp1in26::{DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH};
-use crate::graphics::{Display, DisplayRotation};
+use crate::graphics::{Display, DisplayRotation, RasterOp};
 
 /// 
 pub struct Display1in26 {
     buffer: [u8; (WIDTH  * HEIGHT /8 ) as usize],
     rotation: DisplayRotation,
+    raster_op: RasterOp,
 }
 
 impl Default for Display1in26 {
@@ -19,6 +23,7 @@ impl Default for Display1in26 {
             buffer: [0x00;
                 WIDTH as usize * HEIGHT as usize / 8],
             rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
         }
     }
 }
@@ -45,6 +50,40 @@ impl OriginDimensions for Display1in26 {
     }
 }
 
+impl Snapshot for Display1in26 {
+    const LAYOUT: BufferLayout = BufferLayout::Rows;
+    const BPP: u8 = 1;
+
+    fn page_data(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn page_data_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn snapshot_rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_snapshot_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+}
+
+impl GetPixel for Display1in26 {
+    type Color = BinaryColor;
+
+    fn pixel(&self, point: Point) -> Option<BinaryColor> {
+        if crate::graphics::outside_display(point, WIDTH, HEIGHT, self.rotation) {
+            return None;
+        }
+        let (index, bit) =
+            find_position(point.x as u32, point.y as u32, WIDTH, HEIGHT, self.rotation);
+        Some(BinaryColor::from(self.buffer[index as usize] & bit != 0))
+    }
+}
+
 
 impl Display for Display1in26 {
     fn buffer(&self) -> &[u8] {
@@ -63,6 +102,13 @@ impl Display for Display1in26 {
         self.rotation
     }
 
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
 
     fn draw_helper(
         &mut self,
@@ -71,6 +117,7 @@ impl Display for Display1in26 {
         pixel: Pixel<BinaryColor>,
     ) -> Result<(), Self::Error> {
         let rotation = self.rotation();
+        let op = self.raster_op();
         let buffer = self.get_mut_buffer();
 
         let Pixel(point, color) = pixel;
@@ -85,16 +132,11 @@ impl Display for Display1in26 {
         let index = index as usize;
 
         // "Draw" the Pixel on that bit
-        match color {
-            BinaryColor::On => {
-                // clear bit in bw-buffer -> black
-                buffer[index] |= bit;
-            }
-            BinaryColor::Off => {
-                // set bit in bw-buffer -> white
-                buffer[index] &= !bit;
-            },
-        }
+        let ink = match color {
+            BinaryColor::On => 0xFF,
+            BinaryColor::Off => 0x00,
+        };
+        buffer[index] = op.apply(buffer[index], ink, bit);
 
         Ok(())
     }
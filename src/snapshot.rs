@@ -0,0 +1,190 @@
+//! Saving and restoring framebuffers as a small self describing binary blob
+//!
+//! The blob starts with a header of [HEADER_LEN] bytes, all numbers are little endian:
+//!
+//! | offset | size | content                       |
+//! |--------|------|-------------------------------|
+//! | 0      | 4    | magic `LCDF`                  |
+//! | 4      | 1    | format version, currently 1   |
+//! | 5      | 1    | [BufferLayout] of the data    |
+//! | 6      | 1    | bits per pixel                |
+//! | 7      | 1    | rotation, 0-3 for 0-270°      |
+//! | 8      | 2    | width                         |
+//! | 10     | 2    | height                        |
+//! | 12     | 4    | length of the page data       |
+//!
+//! followed by the page data exactly as it is sent to the lcd.
+
+use embedded_graphics_core::geometry::OriginDimensions;
+
+use crate::graphics::DisplayRotation;
+
+/// Length of the header in front of the page data
+pub const HEADER_LEN: usize = 16;
+
+const MAGIC: &[u8; 4] = b"LCDF";
+const VERSION: u8 = 1;
+
+/// How the pixels are arranged in the buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BufferLayout {
+    /// Rows of bytes, 8 pixels per byte (sharp memory lcd)
+    Rows = 1,
+    /// Pages of 4 lines, one byte with 4 pixels of 2 bits per column (uc1638)
+    InterleavedPages = 2,
+    /// Pages of 8 lines, two bytes with one bit plane each per column (st7571)
+    SplitPlanePages = 3,
+}
+
+/// Error when saving or restoring a framebuffer
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The output buffer or the blob is shorter than needed
+    TooShort,
+    /// The blob doesn't start with a valid header
+    InvalidHeader,
+    /// The blob was saved from a framebuffer with a different layout or size
+    Mismatch,
+    /// The framebuffer is too large to be described by the header
+    TooLarge,
+}
+
+impl core::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SnapshotError::TooShort => write!(f, "Buffer too short for the framebuffer"),
+            SnapshotError::InvalidHeader => write!(f, "Not a framebuffer snapshot"),
+            SnapshotError::Mismatch => write!(f, "Snapshot doesn't fit the framebuffer"),
+            SnapshotError::TooLarge => write!(f, "Framebuffer too large for a snapshot"),
+        }
+    }
+}
+
+fn rotation_to_u8(rotation: DisplayRotation) -> u8 {
+    match rotation {
+        DisplayRotation::Rotate0 => 0,
+        DisplayRotation::Rotate90 => 1,
+        DisplayRotation::Rotate180 => 2,
+        DisplayRotation::Rotate270 => 3,
+    }
+}
+
+fn rotation_from_u8(value: u8) -> Option<DisplayRotation> {
+    match value {
+        0 => Some(DisplayRotation::Rotate0),
+        1 => Some(DisplayRotation::Rotate90),
+        2 => Some(DisplayRotation::Rotate180),
+        3 => Some(DisplayRotation::Rotate270),
+        _ => None,
+    }
+}
+
+/// Framebuffers which can be saved into and restored from a blob
+pub trait Snapshot: OriginDimensions {
+    /// Arrangement of the pixels in the buffer
+    const LAYOUT: BufferLayout;
+    /// Bits per pixel
+    const BPP: u8;
+
+    /// Returns the raw page data
+    fn page_data(&self) -> &[u8];
+
+    /// Returns the raw page data for writing
+    fn page_data_mut(&mut self) -> &mut [u8];
+
+    /// Get the rotation which is stored in the blob
+    fn snapshot_rotation(&self) -> DisplayRotation;
+
+    /// Sets the rotation read from the blob
+    fn set_snapshot_rotation(&mut self, rotation: DisplayRotation);
+
+    /// Number of bytes needed by [Snapshot::save]
+    fn snapshot_len(&self) -> usize {
+        HEADER_LEN + self.page_data().len()
+    }
+
+    /// Writes header and page data into `out`, returns the number of bytes written
+    fn save(&self, out: &mut [u8]) -> Result<usize, SnapshotError> {
+        let len = self.snapshot_len();
+        let out = out.get_mut(..len).ok_or(SnapshotError::TooShort)?;
+        let size = self.size();
+        let data = self.page_data();
+        let width = u16::try_from(size.width).map_err(|_| SnapshotError::TooLarge)?;
+        let height = u16::try_from(size.height).map_err(|_| SnapshotError::TooLarge)?;
+        let data_len = u32::try_from(data.len()).map_err(|_| SnapshotError::TooLarge)?;
+
+        out[0..4].copy_from_slice(MAGIC);
+        out[4] = VERSION;
+        out[5] = Self::LAYOUT as u8;
+        out[6] = Self::BPP;
+        out[7] = rotation_to_u8(self.snapshot_rotation());
+        out[8..10].copy_from_slice(&width.to_le_bytes());
+        out[10..12].copy_from_slice(&height.to_le_bytes());
+        out[12..16].copy_from_slice(&data_len.to_le_bytes());
+        out[HEADER_LEN..].copy_from_slice(data);
+        Ok(len)
+    }
+
+    /// Checks the header of `blob` against this framebuffer and loads the page data and rotation
+    ///
+    /// The framebuffer is left untouched if an error is returned.
+    fn restore(&mut self, blob: &[u8]) -> Result<(), SnapshotError> {
+        let header = blob.get(..HEADER_LEN).ok_or(SnapshotError::TooShort)?;
+        if &header[0..4] != MAGIC || header[4] != VERSION {
+            return Err(SnapshotError::InvalidHeader);
+        }
+        let rotation = rotation_from_u8(header[7]).ok_or(SnapshotError::InvalidHeader)?;
+        let width = u16::from_le_bytes([header[8], header[9]]) as u32;
+        let height = u16::from_le_bytes([header[10], header[11]]) as u32;
+        let len = u32::from_le_bytes([header[12], header[13], header[14], header[15]]) as usize;
+
+        let size = self.size();
+        if header[5] != Self::LAYOUT as u8
+            || header[6] != Self::BPP
+            || width != size.width
+            || height != size.height
+            || len != self.page_data().len()
+        {
+            return Err(SnapshotError::Mismatch);
+        }
+        let data = blob
+            .get(HEADER_LEN..HEADER_LEN + len)
+            .ok_or(SnapshotError::TooShort)?;
+
+        self.page_data_mut().copy_from_slice(data);
+        self.set_snapshot_rotation(rotation);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "uc1638"))]
+mod tests {
+    use super::*;
+    use crate::color::TwoBitColor;
+    use crate::graphics::TwoBitColorDisplay;
+    use crate::uc1638::prelude::Display2in7;
+    use embedded_graphics::image::GetPixel;
+    use embedded_graphics::prelude::*;
+
+    #[test]
+    fn save_and_restore() {
+        let mut display = Display2in7::default();
+        display.set_rotation(DisplayRotation::Rotate180);
+        let _ = Pixel(Point::new(1, 2), TwoBitColor::Gray2).draw(&mut display);
+
+        let mut blob = [0u8; HEADER_LEN + 240 * 96 / 4];
+        assert_eq!(display.save(&mut blob[..20]), Err(SnapshotError::TooShort));
+        assert_eq!(display.save(&mut blob), Ok(blob.len()));
+        assert_eq!(&blob[..8], &[b'L', b'C', b'D', b'F', 1, 2, 2, 2]);
+
+        let mut restored = Display2in7::default();
+        assert_eq!(restored.restore(&blob), Ok(()));
+        assert_eq!(restored.buffer(), display.buffer());
+        assert_eq!(restored.pixel(Point::new(1, 2)), Some(TwoBitColor::Gray2));
+
+        blob[8] = 0;
+        assert_eq!(restored.restore(&blob), Err(SnapshotError::Mismatch));
+        blob[0] = 0;
+        assert_eq!(restored.restore(&blob), Err(SnapshotError::InvalidHeader));
+    }
+}
@@ -1,16 +1,129 @@
-
-use crate::traits;
-
+//! Commands of the ST7571
+//!
+//! Every command is sent with DC low, commands with a parameter send it as second byte.
 
 #[allow(dead_code)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub(crate) enum Command {
-    
+    /// Turns the display on or off, the RAM is kept
+    DisplayOn(bool),
+    /// RAM line shown on the first COM, 0-127
+    StartLine(u8),
+    /// COM which scans the first line, 0-127
+    Com0(u8),
+    /// Number of scanned lines, including the icon line
+    Duty(u8),
+    /// Inverts the drive polarity every n+1 lines, 0-31
+    NLineInversion(u8),
+    /// Back to frame inversion
+    ReleaseNLineInversion,
+    /// Internal regulator resistor ratio, 0-7
+    RegulatorRatio(u8),
+    /// Turns on the booster, the voltage regulator and the voltage follower
+    PowerControl {
+        /// Booster circuit
+        booster: bool,
+        /// Voltage regulator
+        regulator: bool,
+        /// Voltage follower
+        follower: bool,
+    },
+    /// LCD bias, 0 (1/5) to 7 (1/12)
+    Bias(u8),
+    /// Electronic volume (contrast), 0-63
+    ElectronicVolume(u8),
+    /// Frame rate (0-15) and booster efficiency (0-3)
+    ModeSet {
+        /// Frame rate step
+        frame_rate: u8,
+        /// Booster efficiency level
+        booster: u8,
+    },
+    /// Scans the SEG outputs from 127 to 0
+    SegReverse(bool),
+    /// Scans the COM outputs from 127 to 0
+    ComReverse(bool),
+    /// Shows set pixels as light
+    InverseDisplay(bool),
+    /// Turns on all pixels regardless of the RAM
+    AllPixelsOn(bool),
+    /// Starts the internal oscillator
+    OscillatorOn,
+    /// Stops the oscillator and the power circuits, the RAM is kept
+    PowerSave(bool),
+    /// Leaves power save
+    ReleasePowerSave,
+    /// Shows the icon line and selects it for the following data
+    Icon(bool),
+    /// Page for the following data, 0-15
+    Page(u8),
+    /// Column for the following data, 0-127
+    Column(u8),
+    /// Enters extension command set 3
+    Extension3,
+    /// Four gray levels (true) or black and white (false), extension set 3 only
+    GrayMode(bool),
+    /// Leaves an extension command set
+    ExitExtension,
+    /// Software reset
+    Reset,
+    /// No operation
+    Nop,
+}
+
+impl Command {
+    /// Second byte of two byte commands
+    pub(crate) fn parameter(self) -> Option<u8> {
+        match self {
+            Command::StartLine(line) => Some(line & 0x7f),
+            Command::Com0(com) => Some(com & 0x7f),
+            Command::Duty(duty) => Some(duty),
+            Command::NLineInversion(lines) => Some(lines & 0x1f),
+            Command::ElectronicVolume(volume) => Some(volume & 0x3f),
+            Command::ModeSet {
+                frame_rate,
+                booster,
+            } => Some((frame_rate & 0x0f) << 4 | (booster & 0x03) << 2),
+            Command::Column(column) => Some(column & 0x0f),
+            _ => None,
+        }
+    }
 }
 
 impl crate::Command for Command {
     /// Returns the address of the command
     fn address(self) -> u8 {
-        self as u8
+        match self {
+            Command::DisplayOn(on) => 0xAE | on as u8,
+            Command::StartLine(_) => 0x40,
+            Command::Com0(_) => 0x44,
+            Command::Duty(_) => 0x48,
+            Command::NLineInversion(_) => 0x4C,
+            Command::ReleaseNLineInversion => 0xE4,
+            Command::RegulatorRatio(ratio) => 0x20 | (ratio & 0x07),
+            Command::PowerControl {
+                booster,
+                regulator,
+                follower,
+            } => 0x28 | (booster as u8) << 2 | (regulator as u8) << 1 | follower as u8,
+            Command::Bias(bias) => 0x50 | (bias & 0x07),
+            Command::ElectronicVolume(_) => 0x81,
+            Command::ModeSet { .. } => 0x38,
+            Command::SegReverse(reverse) => 0xA0 | reverse as u8,
+            Command::ComReverse(reverse) => 0xC0 | (reverse as u8) << 3,
+            Command::InverseDisplay(inverse) => 0xA6 | inverse as u8,
+            Command::AllPixelsOn(on) => 0xA4 | on as u8,
+            Command::OscillatorOn => 0xAB,
+            Command::PowerSave(save) => 0xA8 | save as u8,
+            Command::ReleasePowerSave => 0xE1,
+            Command::Icon(on) => 0xA2 | on as u8,
+            Command::Page(page) => 0xB0 | (page & 0x0f),
+            Command::Column(column) => 0x10 | (column >> 4 & 0x07),
+            Command::Extension3 => 0x7B,
+            Command::GrayMode(gray) => 0x11 - gray as u8,
+            Command::ExitExtension => 0x00,
+            Command::Reset => 0xE2,
+            Command::Nop => 0xE3,
+        }
     }
 }
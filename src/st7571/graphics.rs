@@ -2,28 +2,90 @@ use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::geometry::Size;
 use embedded_graphics::Pixel;
 use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::geometry::Point;
+use embedded_graphics::image::GetPixel;
+use crate::snapshot::{BufferLayout, Snapshot};
 
-use crate::color::TwoBitColor;
-use crate::graphics::TwoBitColorDisplay;
-use crate::st7571::{DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH};
-use crate::graphics::{Display, DisplayRotation};
+use crate::color::{GrayMap, TwoBitColor};
+use crate::graphics::{GrayEncoding, TwoBitColorDisplay};
+use crate::st7571::{DEFAULT_BACKGROUND_COLOR, HEIGHT, RAM_HEIGHT, RAM_WIDTH, WIDTH};
+use crate::graphics::{Display, DisplayRotation, PageLayout, RasterOp};
+use embedded_graphics_core::pixelcolor::BinaryColor;
+use embedded_graphics::primitives::Rectangle;
 
 /// 
 pub struct Display2in3 {
     buffer: [u8; (WIDTH  * HEIGHT /4 ) as usize],
     rotation: DisplayRotation,
+    raster_op: RasterOp,
+    gray_map: GrayMap,
 }
 
 impl Default for Display2in3 {
     fn default() -> Self {
+        let mut buffer = [0; WIDTH as usize * HEIGHT as usize / 4];
+        Self::fill(&mut buffer, DEFAULT_BACKGROUND_COLOR);
         Display2in3 {
-            buffer: [DEFAULT_BACKGROUND_COLOR.get_byte_value();
-                WIDTH as usize * HEIGHT as usize / 4],
+            buffer,
             rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
+            gray_map: GrayMap::default(),
         }
     }
 }
 
+/// The high bit of the 2 bit code is stored in the first byte of a column,
+/// the low bit in the second one: 00 white, 01 gray1, 10 gray2, 11 black
+impl GrayEncoding for Display2in3 {
+    const COLUMN_BYTES: usize = 2;
+
+    fn code(color: TwoBitColor) -> u8 {
+        match color {
+            TwoBitColor::White => 0b00,
+            TwoBitColor::Gray1 => 0b01,
+            TwoBitColor::Gray2 => 0b10,
+            TwoBitColor::Black => 0b11,
+        }
+    }
+
+    fn decode(code: u8) -> TwoBitColor {
+        match code & 0b11 {
+            0b00 => TwoBitColor::White,
+            0b01 => TwoBitColor::Gray1,
+            0b10 => TwoBitColor::Gray2,
+            _ => TwoBitColor::Black,
+        }
+    }
+
+    fn fill_pattern(color: TwoBitColor) -> [u8; 2] {
+        let code = Self::code(color);
+        [
+            if code & 0b10 != 0 { 0xFF } else { 0x00 },
+            if code & 0b01 != 0 { 0xFF } else { 0x00 },
+        ]
+    }
+}
+
+
+impl Display2in3 {
+    const LAYOUT: PageLayout = PageLayout {
+        width: WIDTH,
+        rows: 8,
+        bytes: 2,
+    };
+
+    /// Copies the pixels of `src` so that its top left corner ends up at `dst`
+    ///
+    /// Whole bytes are moved if the rows are aligned to the pages of 8 lines.
+    pub fn copy_region(&mut self, src: Rectangle, dst: Point) {
+        crate::graphics::copy_region(self, Self::LAYOUT, src, dst);
+    }
+
+    /// Moves the content of `area` by `dx`, `dy` and fills the uncovered part with `fill`
+    pub fn scroll_region(&mut self, area: Rectangle, dx: i32, dy: i32, fill: TwoBitColor) {
+        crate::graphics::scroll_region(self, Self::LAYOUT, area, dx, dy, fill);
+    }
+}
 
 impl DrawTarget for Display2in3 {
     type Color = TwoBitColor;
@@ -46,6 +108,43 @@ impl OriginDimensions for Display2in3 {
     }
 }
 
+impl Snapshot for Display2in3 {
+    const LAYOUT: BufferLayout = BufferLayout::SplitPlanePages;
+    const BPP: u8 = 2;
+
+    fn page_data(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn page_data_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn snapshot_rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_snapshot_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+}
+
+impl GetPixel for Display2in3 {
+    type Color = TwoBitColor;
+
+    fn pixel(&self, point: Point) -> Option<TwoBitColor> {
+        if crate::graphics::outside_display(point, WIDTH, HEIGHT, self.rotation) {
+            return None;
+        }
+        let (index, bit) =
+            find_position(point.x as u32, point.y as u32, WIDTH, HEIGHT, self.rotation);
+        let index = index as usize;
+        let high = (self.buffer[index] & bit != 0) as u8;
+        let low = (self.buffer[index + 1] & bit != 0) as u8;
+        Some(self.gray_map.unmap(Self::decode(high << 1 | low)))
+    }
+}
+
 
 impl TwoBitColorDisplay for Display2in3 {
     fn buffer(&self) -> &[u8] {
@@ -64,6 +163,21 @@ impl TwoBitColorDisplay for Display2in3 {
         self.rotation
     }
 
+    fn set_gray_map(&mut self, map: GrayMap) {
+        self.gray_map = map;
+    }
+
+    fn gray_map(&self) -> GrayMap {
+        self.gray_map
+    }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
 
     fn draw_helper(
         &mut self,
@@ -72,9 +186,11 @@ impl TwoBitColorDisplay for Display2in3 {
         pixel: Pixel<TwoBitColor>,
     ) -> Result<(), Self::Error> {
         let rotation = self.rotation();
+        let op = self.raster_op();
+        let color = self.gray_map().map(pixel.1);
         let buffer = self.get_mut_buffer();
 
-        let Pixel(point, color) = pixel;
+        let Pixel(point, _) = pixel;
         if crate::graphics::outside_display(point, width, height, rotation) {
             return Ok(());
         }
@@ -85,32 +201,403 @@ impl TwoBitColorDisplay for Display2in3 {
            find_position(point.x as u32, point.y as u32, width, height, rotation);
         let index = index as usize;
 
-        // "Draw" the Pixel on that bit
-        match color {
-            TwoBitColor::Black => {
-                // clear bit in bw-buffer -> black
-
-                buffer[index] |= bit;
-                buffer[index+1] |= bit;
-            }
-            TwoBitColor::White => {
-                // set bit in bw-buffer -> white
-                buffer[index] &= !bit;
-                buffer[index+1] &= !bit;
-            },
-            TwoBitColor::Gray1 => {
-                buffer[index] &= !bit;
-                buffer[index+1] |= bit;
-            },
-            TwoBitColor::Gray2 =>{
-                buffer[index] |= bit;
-                buffer[index+1] &= !bit;
-            }
+        // "Draw" the Pixel on that bit, the high bit of the code goes to the first byte
+        // and the low bit to the second one
+        let [high, low] = Self::fill_pattern(color);
+        buffer[index] = op.apply(buffer[index], high, bit);
+        buffer[index + 1] = op.apply(buffer[index + 1], low, bit);
+
+        Ok(())
+    }
+}
+/// Black and white framebuffer of the 128x96 module for [Mode::Mono](crate::st7571::Mode::Mono)
+///
+/// One byte per column of a page of 8 lines, the top line in bit 0, a set bit is black.
+/// Other glass sizes use [VarDisplay2in3Mono].
+pub struct Display2in3Mono {
+    buffer: [u8; (WIDTH * HEIGHT / 8) as usize],
+    rotation: DisplayRotation,
+    raster_op: RasterOp,
+}
+
+impl Default for Display2in3Mono {
+    fn default() -> Self {
+        let mut display = Display2in3Mono {
+            buffer: [0; WIDTH as usize * HEIGHT as usize / 8],
+            rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
+        };
+        display.clear_buffer(BinaryColor::from(DEFAULT_BACKGROUND_COLOR));
+        display
+    }
+}
+
+impl DrawTarget for Display2in3Mono {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            self.draw_helper(WIDTH, HEIGHT, pixel)?;
+        }
+        Ok(())
+    }
+}
+
+impl OriginDimensions for Display2in3Mono {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl GetPixel for Display2in3Mono {
+    type Color = BinaryColor;
+
+    fn pixel(&self, point: Point) -> Option<BinaryColor> {
+        if crate::graphics::outside_display(point, WIDTH, HEIGHT, self.rotation) {
+            return None;
+        }
+        let (index, bit) =
+            find_mono_position(point.x as u32, point.y as u32, WIDTH, HEIGHT, self.rotation);
+        Some(BinaryColor::from(self.buffer[index as usize] & bit != 0))
+    }
+}
+
+impl Display for Display2in3Mono {
+    fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
+
+    fn draw_helper(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel: Pixel<BinaryColor>,
+    ) -> Result<(), Self::Error> {
+        let rotation = self.rotation();
+        let op = self.raster_op();
+        let buffer = self.get_mut_buffer();
+
+        let Pixel(point, color) = pixel;
+        if crate::graphics::outside_display(point, width, height, rotation) {
+            return Ok(());
+        }
+
+        let (index, bit) =
+            find_mono_position(point.x as u32, point.y as u32, width, height, rotation);
+        let index = index as usize;
+
+        let ink = match color {
+            BinaryColor::On => 0xFF,
+            BinaryColor::Off => 0x00,
+        };
+        buffer[index] = op.apply(buffer[index], ink, bit);
+
+        Ok(())
+    }
+}
+
+/// Bytes of a framebuffer for a `width` x `height` ST7571 panel, height rounded up to whole pages
+pub const fn buffer_len(width: u32, height: u32) -> usize {
+    (width * 2 * height.div_ceil(8)) as usize
+}
+
+/// Bytes of a [Mode::Mono](crate::st7571::Mode::Mono) framebuffer for a `width` x `height`
+/// ST7571 panel, height rounded up to whole pages
+pub const fn mono_buffer_len(width: u32, height: u32) -> usize {
+    (width * height.div_ceil(8)) as usize
+}
+
+/// A variable sized ST7571 display without a predefined buffer
+///
+/// Same layout as [Display2in3] for any glass up to the 128x128 RAM. The drivers expect the
+/// framebuffer to match the configured glass, so this is needed for every glass which isn't
+/// 128x96, e.g. a full 128x128 module:
+///
+/// ```rust,ignore
+/// let mut buffer = [0u8; buffer_len(128, 128)];
+/// let mut display = VarDisplay2in3::new(128, 128, &mut buffer);
+/// ```
+pub struct VarDisplay2in3<'a> {
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    raster_op: RasterOp,
+    gray_map: GrayMap,
+    buffer: &'a mut [u8],
+}
+
+impl<'a> VarDisplay2in3<'a> {
+    /// Create a new variable sized display.
+    ///
+    /// Buffersize must be at least [buffer_len] bytes, only that many are used.
+    pub fn new(width: u32, height: u32, buffer: &'a mut [u8]) -> VarDisplay2in3<'a> {
+        assert!(width <= RAM_WIDTH && height <= RAM_HEIGHT);
+        assert!(buffer.len() >= buffer_len(width, height));
+        let buffer = &mut buffer[..buffer_len(width, height)];
+        VarDisplay2in3 {
+            width,
+            height,
+            rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
+            gray_map: GrayMap::default(),
+            buffer,
+        }
+    }
+}
+
+impl<'a> GrayEncoding for VarDisplay2in3<'a> {
+    const COLUMN_BYTES: usize = Display2in3::COLUMN_BYTES;
+
+    fn code(color: TwoBitColor) -> u8 {
+        Display2in3::code(color)
+    }
+
+    fn decode(code: u8) -> TwoBitColor {
+        Display2in3::decode(code)
+    }
+
+    fn fill_pattern(color: TwoBitColor) -> [u8; 2] {
+        Display2in3::fill_pattern(color)
+    }
+}
+
+impl<'a> DrawTarget for VarDisplay2in3<'a> {
+    type Color = TwoBitColor;
+    type Error = core::convert::Infallible;
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            self.draw_helper(self.width, self.height, pixel)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> OriginDimensions for VarDisplay2in3<'a> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl<'a> GetPixel for VarDisplay2in3<'a> {
+    type Color = TwoBitColor;
+
+    fn pixel(&self, point: Point) -> Option<TwoBitColor> {
+        if crate::graphics::outside_display(point, self.width, self.height, self.rotation) {
+            return None;
+        }
+        let (index, bit) =
+            find_position(point.x as u32, point.y as u32, self.width, self.height, self.rotation);
+        let index = index as usize;
+        let high = (self.buffer[index] & bit != 0) as u8;
+        let low = (self.buffer[index + 1] & bit != 0) as u8;
+        Some(self.gray_map.unmap(Self::decode(high << 1 | low)))
+    }
+}
+
+impl<'a> TwoBitColorDisplay for VarDisplay2in3<'a> {
+    fn buffer(&self) -> &[u8] {
+        self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_gray_map(&mut self, map: GrayMap) {
+        self.gray_map = map;
+    }
+
+    fn gray_map(&self) -> GrayMap {
+        self.gray_map
+    }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
+
+    fn draw_helper(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel: Pixel<TwoBitColor>,
+    ) -> Result<(), Self::Error> {
+        let rotation = self.rotation();
+        let op = self.raster_op();
+        let color = self.gray_map().map(pixel.1);
+        let buffer = self.get_mut_buffer();
+
+        let Pixel(point, _) = pixel;
+        if crate::graphics::outside_display(point, width, height, rotation) {
+            return Ok(());
+        }
+
+        let (index, bit) =
+           find_position(point.x as u32, point.y as u32, width, height, rotation);
+        let index = index as usize;
+
+        let [high, low] = Self::fill_pattern(color);
+        buffer[index] = op.apply(buffer[index], high, bit);
+        buffer[index + 1] = op.apply(buffer[index + 1], low, bit);
+
+        Ok(())
+    }
+}
+
+/// A variable sized black and white ST7571 display without a predefined buffer
+///
+/// Same layout as [Display2in3Mono] for any glass up to the 128x128 RAM, needed for every
+/// glass which isn't 128x96.
+pub struct VarDisplay2in3Mono<'a> {
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    raster_op: RasterOp,
+    buffer: &'a mut [u8],
+}
+
+impl<'a> VarDisplay2in3Mono<'a> {
+    /// Create a new variable sized display.
+    ///
+    /// Buffersize must be at least [mono_buffer_len] bytes, only that many are used.
+    pub fn new(width: u32, height: u32, buffer: &'a mut [u8]) -> VarDisplay2in3Mono<'a> {
+        assert!(width <= RAM_WIDTH && height <= RAM_HEIGHT);
+        assert!(buffer.len() >= mono_buffer_len(width, height));
+        let buffer = &mut buffer[..mono_buffer_len(width, height)];
+        VarDisplay2in3Mono {
+            width,
+            height,
+            rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
+            buffer,
+        }
+    }
+}
+
+impl<'a> DrawTarget for VarDisplay2in3Mono<'a> {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            self.draw_helper(self.width, self.height, pixel)?;
         }
+        Ok(())
+    }
+}
+
+impl<'a> OriginDimensions for VarDisplay2in3Mono<'a> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl<'a> GetPixel for VarDisplay2in3Mono<'a> {
+    type Color = BinaryColor;
+
+    fn pixel(&self, point: Point) -> Option<BinaryColor> {
+        if crate::graphics::outside_display(point, self.width, self.height, self.rotation) {
+            return None;
+        }
+        let (index, bit) =
+            find_mono_position(point.x as u32, point.y as u32, self.width, self.height, self.rotation);
+        Some(BinaryColor::from(self.buffer[index as usize] & bit != 0))
+    }
+}
+
+impl<'a> Display for VarDisplay2in3Mono<'a> {
+    fn buffer(&self) -> &[u8] {
+        self.buffer
+    }
+
+    fn get_mut_buffer(&mut self) -> &mut [u8] {
+        self.buffer
+    }
+
+    fn set_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+
+    fn rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
+
+    fn draw_helper(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixel: Pixel<BinaryColor>,
+    ) -> Result<(), Self::Error> {
+        let rotation = self.rotation();
+        let op = self.raster_op();
+        let buffer = self.get_mut_buffer();
+
+        let Pixel(point, color) = pixel;
+        if crate::graphics::outside_display(point, width, height, rotation) {
+            return Ok(());
+        }
+
+        let (index, bit) =
+            find_mono_position(point.x as u32, point.y as u32, width, height, rotation);
+        let index = index as usize;
+
+        let ink = match color {
+            BinaryColor::On => 0xFF,
+            BinaryColor::Off => 0x00,
+        };
+        buffer[index] = op.apply(buffer[index], ink, bit);
 
         Ok(())
     }
 }
+
 #[rustfmt::skip]
 fn find_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotation) -> (u32, u8) {
     let (nx, ny) = crate::graphics::find_rotation(x, y, width, height, rotation);
@@ -122,4 +609,77 @@ fn find_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotat
         (width * 2) * row + (col * 2),
         0x01 << (ny %8)
     )
-}
\ No newline at end of file
+}
+#[rustfmt::skip]
+fn find_mono_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotation) -> (u32, u8) {
+    let (nx, ny) = crate::graphics::find_rotation(x, y, width, height, rotation);
+    //每列的八个像素放在一个字节中
+    (
+        width * (ny / 8) + nx,
+        0x01 << (ny % 8)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::prelude::*;
+
+    #[test]
+    fn fill_produces_gray() {
+        let mut display = Display2in3::default();
+        for color in [TwoBitColor::Gray1, TwoBitColor::Gray2, TwoBitColor::Black] {
+            Display2in3::fill(display.get_mut_buffer(), color);
+            assert_eq!(display.pixel(Point::new(0, 0)), Some(color));
+            assert_eq!(display.pixel(Point::new(5, 13)), Some(color));
+        }
+    }
+
+    #[test]
+    fn xor_black_inverts_gray_code() {
+        let mut display = Display2in3::default();
+        let _ = Pixel(Point::new(3, 1), TwoBitColor::Gray1).draw(&mut display);
+        assert_eq!(&display.buffer()[6..8], &[0b0000_0000, 0b0000_0010]);
+
+        display.set_raster_op(RasterOp::Xor);
+        let _ = Pixel(Point::new(3, 1), TwoBitColor::Black).draw(&mut display);
+        assert_eq!(&display.buffer()[6..8], &[0b0000_0010, 0b0000_0000]);
+    }
+
+    #[test]
+    fn mono_uses_one_byte_per_column() {
+        let mut display = Display2in3Mono::default();
+        assert_eq!(display.buffer().len(), 128 * 96 / 8);
+        let _ = Pixel(Point::new(3, 9), BinaryColor::On).draw(&mut display);
+        assert_eq!(display.buffer()[128 + 3], 0b0000_0010);
+        assert_eq!(display.pixel(Point::new(3, 9)), Some(BinaryColor::On));
+        assert_eq!(display.pixel(Point::new(3, 8)), Some(BinaryColor::Off));
+    }
+
+    #[test]
+    fn var_display_covers_full_ram() {
+        let mut buffer = [0u8; buffer_len(RAM_WIDTH, RAM_HEIGHT)];
+        assert_eq!(buffer.len(), 128 * 128 / 4);
+        let mut display = VarDisplay2in3::new(RAM_WIDTH, RAM_HEIGHT, &mut buffer);
+        let _ = Pixel(Point::new(127, 127), TwoBitColor::Gray2).draw(&mut display);
+        assert_eq!(display.pixel(Point::new(127, 127)), Some(TwoBitColor::Gray2));
+        assert_eq!(display.pixel(Point::new(0, 128)), None);
+        let last = buffer.len() - 2;
+        assert_eq!(&buffer[last..], &[0x80, 0x00]);
+
+        // a glass which doesn't end on a page boundary
+        assert_eq!(buffer_len(100, 20), 100 * 2 * 3);
+    }
+
+    #[test]
+    fn var_mono_display_covers_full_ram() {
+        let mut buffer = [0u8; mono_buffer_len(RAM_WIDTH, RAM_HEIGHT)];
+        assert_eq!(buffer.len(), 128 * 128 / 8);
+        let mut display = VarDisplay2in3Mono::new(RAM_WIDTH, RAM_HEIGHT, &mut buffer);
+        let _ = Pixel(Point::new(127, 127), BinaryColor::On).draw(&mut display);
+        assert_eq!(display.pixel(Point::new(127, 127)), Some(BinaryColor::On));
+        assert_eq!(display.pixel(Point::new(0, 128)), None);
+        assert_eq!(buffer[buffer.len() - 1], 0x80);
+        assert_eq!(mono_buffer_len(100, 20), 100 * 3);
+    }
+}
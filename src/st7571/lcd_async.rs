@@ -0,0 +1,697 @@
+use embedded_graphics::primitives::Rectangle;
+use embedded_hal_async::{delay::DelayNs, spi::SpiDevice};
+use embedded_hal_v2::digital::OutputPin;
+
+use crate::color::TwoBitColor;
+use crate::interface_async::DisplayInterface;
+use crate::st7571::command::Command;
+#[cfg(doc)]
+use crate::st7571::prelude::Display2in3;
+use crate::st7571::{
+    fill_pattern, icon_pattern, init_sequence, partial_sequence, scrolled_line, Mode, St7571Config,
+    State, Window, DEFAULT_BACKGROUND_COLOR, POWER_OFF_MS, POWER_RAMP,
+};
+use crate::traits_async::{InternalWiAdditions, WaveshareDisplay};
+
+/// Lcd2in3 driver
+///
+pub struct Lcd2in3<SPI, DC, RST, DELAY> {
+    /// Connection Interface
+    interface: DisplayInterface<SPI, DC, RST, DELAY>,
+    /// Background Color
+    color: TwoBitColor,
+    /// Settings sent during init
+    config: St7571Config,
+    /// Icon segments, bit n is glass column n of the icon line
+    icons: u128,
+    /// Switches changed after init
+    state: State,
+}
+
+impl<SPI, DC, RST, DELAY> InternalWiAdditions<SPI, DC, RST, DELAY> for Lcd2in3<SPI, DC, RST, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    DELAY: DelayNs,
+{
+    async fn init(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.interface.reset(delay, 10).await?;
+        self.state = State::default();
+
+        for (command, wait) in init_sequence(&self.config) {
+            self.command(spi, command).await?;
+            if wait > 0 {
+                delay.delay_ms(wait.into()).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<SPI, DC, RST, DELAY> WaveshareDisplay<SPI, DC, RST, DELAY> for Lcd2in3<SPI, DC, RST, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    DELAY: DelayNs,
+{
+    type DisplayColor = TwoBitColor;
+    async fn new(spi: &mut SPI, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
+        Self::with_config(spi, dc, rst, delay, St7571Config::default()).await
+    }
+
+    /// Sends a whole framebuffer of [St7571Config::buffer_len] bytes
+    ///
+    /// Panics if `buffer` has another length, e.g. a [Display2in3] for a glass which
+    /// isn't 128 columns wide.
+    async fn update_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        _delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        assert_eq!(
+            buffer.len(),
+            self.config.buffer_len(),
+            "framebuffer doesn't fit the config"
+        );
+        let window = Window::new(
+            0,
+            0,
+            self.config.width,
+            self.config.height,
+            self.config.mode,
+        );
+        self.send_window(spi, window, buffer, self.config.width)
+            .await
+    }
+
+    /// `buffer` holds the pages of the window, y and height are rounded out to whole pages
+    ///
+    /// The part of the window outside the glass is skipped.
+    async fn update_partial_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), SPI::Error> {
+        match Window::partial(&self.config, buffer, x, y, width, height) {
+            Some(window) => self.send_window(spi, window, buffer, width).await,
+            None => Ok(()),
+        }
+    }
+
+    /// The RAM is shown right away
+    async fn display_frame(
+        &mut self,
+        _spi: &mut SPI,
+        _delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        Ok(())
+    }
+
+    async fn update_and_display_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        self.update_frame(spi, buffer, delay).await
+    }
+
+    async fn clear_frame(&mut self, spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
+        let (pattern, bytes) = fill_pattern(self.color, self.config.mode);
+
+        //每页8行,每列一个或两个字节
+        for page in 0..self.config.height.div_ceil(8) {
+            self.goto(spi, 0, page as u8).await?;
+            for _ in 0..self.config.width {
+                self.interface.data(spi, &pattern[..bytes]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_background_color(&mut self, color: TwoBitColor) {
+        self.color = color;
+    }
+
+    fn background_color(&self) -> &TwoBitColor {
+        &self.color
+    }
+
+    fn width(&self) -> u32 {
+        self.config.width
+    }
+
+    fn height(&self) -> u32 {
+        self.config.height
+    }
+}
+
+impl<SPI, DC, RST, DELAY> Lcd2in3<SPI, DC, RST, DELAY>
+where
+    SPI: SpiDevice,
+    DC: OutputPin,
+    RST: OutputPin,
+    DELAY: DelayNs,
+{
+    /// Creates the driver and initialises the lcd with `config`
+    ///
+    /// Panics if the glass is empty or it and its offsets don't fit into the RAM.
+    pub async fn with_config(
+        spi: &mut SPI,
+        dc: DC,
+        rst: RST,
+        delay: &mut DELAY,
+        config: St7571Config,
+    ) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(dc, rst);
+        let color = DEFAULT_BACKGROUND_COLOR;
+
+        let mut epd = Lcd2in3 {
+            interface,
+            color,
+            config,
+            icons: 0,
+            state: State::default(),
+        };
+
+        epd.init(spi, delay).await?;
+
+        Ok(epd)
+    }
+
+    /// Get the settings used by init
+    pub fn config(&self) -> &St7571Config {
+        &self.config
+    }
+
+    /// Changes the settings and initialises the lcd again
+    pub async fn set_config(
+        &mut self,
+        spi: &mut SPI,
+        delay: &mut DELAY,
+        config: St7571Config,
+    ) -> Result<(), SPI::Error> {
+        self.config = config;
+        self.init(spi, delay).await
+    }
+
+    /// Sets the electronic volume, 0-63, higher is darker
+    pub async fn set_contrast(&mut self, spi: &mut SPI, contrast: u8) -> Result<(), SPI::Error> {
+        self.config.contrast = contrast;
+        self.command(spi, Command::ElectronicVolume(contrast)).await
+    }
+
+    /// Enters power save, the RAM is kept
+    pub async fn sleep(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.set_power_save(spi, true).await
+    }
+
+    /// Leaves power save and shows the RAM again
+    pub async fn wake_up(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.set_power_save(spi, false).await
+    }
+
+    /// Turns the display on or off, the RAM is kept
+    pub async fn set_display_on(&mut self, spi: &mut SPI, on: bool) -> Result<(), SPI::Error> {
+        self.state.on = on;
+        self.command(spi, Command::DisplayOn(on)).await
+    }
+
+    /// Get whether the display is on
+    pub fn display_on(&self) -> bool {
+        self.state.on
+    }
+
+    /// Shows white as black and the grays swapped, without touching the RAM
+    pub async fn set_inverted(&mut self, spi: &mut SPI, inverted: bool) -> Result<(), SPI::Error> {
+        self.state.inverted = inverted;
+        self.command(spi, Command::InverseDisplay(inverted)).await
+    }
+
+    /// Get whether the display is reversed
+    pub fn inverted(&self) -> bool {
+        self.state.inverted
+    }
+
+    /// Turns on all pixels regardless of the RAM, wins over [Self::set_inverted]
+    pub async fn set_all_pixels_on(&mut self, spi: &mut SPI, on: bool) -> Result<(), SPI::Error> {
+        self.state.all_pixels_on = on;
+        self.command(spi, Command::AllPixelsOn(on)).await
+    }
+
+    /// Get whether all pixels are forced on
+    pub fn all_pixels_on(&self) -> bool {
+        self.state.all_pixels_on
+    }
+
+    /// Enters power save with the display off and all pixels on, or leaves it
+    ///
+    /// Leaving restores the display on and all pixels on switches set before.
+    pub async fn set_power_save(&mut self, spi: &mut SPI, save: bool) -> Result<(), SPI::Error> {
+        let state = self.state;
+        if save {
+            self.command(spi, Command::DisplayOn(false)).await?;
+            self.command(spi, Command::AllPixelsOn(true)).await?;
+            self.command(spi, Command::PowerSave(true)).await?;
+        } else {
+            self.command(spi, Command::ReleasePowerSave).await?;
+            self.command(spi, Command::AllPixelsOn(state.all_pixels_on))
+                .await?;
+            self.command(spi, Command::DisplayOn(state.on)).await?;
+        }
+        self.state.power_save = save;
+        Ok(())
+    }
+
+    /// Get whether power save is entered
+    pub fn power_save(&self) -> bool {
+        self.state.power_save
+    }
+
+    /// Scans only `rows` rows from row `first`, the other rows stay blank
+    ///
+    /// Duty and bias are lowered to the band which cuts the power draw, at least 16 lines
+    /// are scanned. The content keeps its place on the glass.
+    pub async fn enter_partial_display(
+        &mut self,
+        spi: &mut SPI,
+        first: u32,
+        rows: u32,
+    ) -> Result<(), SPI::Error> {
+        let first = first.min(self.config.height.saturating_sub(1));
+        let rows = rows.min(self.config.height - first).max(1);
+        self.state.partial = Some((first, rows));
+        self.send_partial(spi).await
+    }
+
+    /// Scans the whole glass again with the duty and bias of the config
+    pub async fn exit_partial_display(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.state.partial = None;
+        self.send_partial(spi).await
+    }
+
+    /// First row and rows of the partial display, `None` if the whole glass is scanned
+    pub fn partial_display(&self) -> Option<(u32, u32)> {
+        self.state.partial
+    }
+
+    async fn send_partial(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        for command in partial_sequence(&self.config, &self.state) {
+            self.command(spi, command).await?;
+        }
+        Ok(())
+    }
+
+    /// Shuts the lcd down so VDD can be removed safely
+    ///
+    /// Turns the display off, then booster, regulator and follower, enters power save and
+    /// waits until the internal power is discharged. After VDD was removed the lcd needs a
+    /// new init, otherwise [Self::power_on] brings it back with the RAM kept.
+    pub async fn power_off(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::DisplayOn(false)).await?;
+        let off = Command::PowerControl {
+            booster: false,
+            regulator: false,
+            follower: false,
+        };
+        self.command(spi, off).await?;
+        self.command(spi, Command::PowerSave(true)).await?;
+        delay.delay_ms(POWER_OFF_MS.into()).await;
+        self.state.on = false;
+        self.state.power_save = true;
+        Ok(())
+    }
+
+    /// Leaves power save, ramps up booster, regulator and follower and turns the display on
+    pub async fn power_on(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::ReleasePowerSave).await?;
+        for (command, wait) in POWER_RAMP {
+            self.command(spi, command).await?;
+            delay.delay_ms(wait.into()).await;
+        }
+        self.command(spi, Command::AllPixelsOn(self.state.all_pixels_on))
+            .await?;
+        self.command(spi, Command::DisplayOn(true)).await?;
+        self.state.on = true;
+        self.state.power_save = false;
+        Ok(())
+    }
+
+    /// Switches between black and white and four gray levels
+    ///
+    /// The RAM is read in the new layout, send the frame of the matching display again.
+    pub async fn set_mode(&mut self, spi: &mut SPI, mode: Mode) -> Result<(), SPI::Error> {
+        self.config.mode = mode;
+        self.command(spi, Command::Extension3).await?;
+        self.command(spi, Command::GrayMode(mode == Mode::Gray4))
+            .await?;
+        self.command(spi, Command::ExitExtension).await
+    }
+
+    /// RAM line shown on the first row
+    pub fn start_line(&self) -> u8 {
+        self.state.start_line
+    }
+
+    /// Shows RAM line `line` on the first row, the RAM wraps after line 127
+    ///
+    /// Moves the content without sending pixel data, the lines below the glass can be
+    /// drawn in advance.
+    pub async fn set_start_line(&mut self, spi: &mut SPI, line: u8) -> Result<(), SPI::Error> {
+        self.state.start_line = scrolled_line(line, 0);
+        let command = self.state.start_line_command();
+        self.command(spi, command).await
+    }
+
+    /// Moves the content up by `lines`, one line every `step_ms`, negative values move it down
+    pub async fn smooth_scroll(
+        &mut self,
+        spi: &mut SPI,
+        delay: &mut DELAY,
+        lines: i32,
+        step_ms: u8,
+    ) -> Result<(), SPI::Error> {
+        for _ in 0..lines.unsigned_abs() {
+            let line = scrolled_line(self.state.start_line, lines.signum());
+            self.set_start_line(spi, line).await?;
+            delay.delay_ms(step_ms.into()).await;
+        }
+        Ok(())
+    }
+
+    /// Icon segments, bit n is glass column n of the icon line
+    pub fn icons(&self) -> u128 {
+        self.icons
+    }
+
+    /// Turns one segment of the icon line on or off, the framebuffer is not touched
+    ///
+    /// `column` is a glass column like x, columns outside the glass are ignored.
+    pub async fn set_icon(
+        &mut self,
+        spi: &mut SPI,
+        column: u8,
+        on: bool,
+    ) -> Result<(), SPI::Error> {
+        let Some(ram_column) = self.config.ram_column(column) else {
+            return Ok(());
+        };
+        let bit = 1u128 << column;
+        let icons = if on {
+            self.icons | bit
+        } else {
+            self.icons & !bit
+        };
+        self.command(spi, Command::Icon(true)).await?;
+        self.command(spi, Command::Column(ram_column)).await?;
+        self.interface
+            .data(spi, icon_pattern(on, self.config.mode))
+            .await?;
+        self.finish_icons(spi, icons).await
+    }
+
+    /// Writes the icon line, bit n is glass column n, bits outside the glass are ignored
+    pub async fn set_icons(&mut self, spi: &mut SPI, icons: u128) -> Result<(), SPI::Error> {
+        let icons = icons & self.config.icon_mask();
+        self.command(spi, Command::Icon(true)).await?;
+        self.command(spi, Command::Column(self.config.column_offset))
+            .await?;
+        for column in 0..self.config.width {
+            self.interface
+                .data(
+                    spi,
+                    icon_pattern(icons >> column & 1 != 0, self.config.mode),
+                )
+                .await?;
+        }
+        self.finish_icons(spi, icons).await
+    }
+
+    /// Turns off all segments of the icon line
+    pub async fn clear_icons(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.set_icons(spi, 0).await
+    }
+
+    /// Keeps the icon line shown while a segment is on
+    async fn finish_icons(&mut self, spi: &mut SPI, icons: u128) -> Result<(), SPI::Error> {
+        self.icons = icons;
+        if icons == 0 {
+            self.command(spi, Command::Icon(false)).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the part of a full framebuffer like [Display2in3] inside `area`
+    ///
+    /// `area` is in unrotated buffer coordinates, its rows are rounded out to whole pages
+    /// of 8 lines and it is clipped to the glass. Panics if `buffer` isn't
+    /// [St7571Config::buffer_len] bytes long.
+    pub async fn update_region(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        area: Rectangle,
+    ) -> Result<(), SPI::Error> {
+        assert_eq!(
+            buffer.len(),
+            self.config.buffer_len(),
+            "framebuffer doesn't fit the config"
+        );
+        match Window::clipped(&self.config, area) {
+            Some(window) => {
+                let stride = self.config.width;
+                self.send_window(spi, window, &buffer[window.offset(stride)..], stride)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the pages of `window`, `buffer` starts with its first column
+    async fn send_window(
+        &mut self,
+        spi: &mut SPI,
+        window: Window,
+        buffer: &[u8],
+        stride: u32,
+    ) -> Result<(), SPI::Error> {
+        for (page, data) in window.pages(buffer, stride) {
+            self.goto(spi, window.x as u8, page).await?;
+            self.interface.data(spi, data).await?;
+        }
+        Ok(())
+    }
+
+    /// X对应玻璃上的列,值范围0到宽度-1,加上配置的列偏移,超出范围会panic
+    /// Y对应页,值范围0-15,每页8行
+    pub async fn goto(&mut self, spi: &mut SPI, x: u8, y: u8) -> Result<(), SPI::Error> {
+        let column = self.config.ram_column(x).expect("column outside the glass");
+        self.command(spi, Command::Page(y)).await?;
+        self.command(spi, Command::Column(column)).await
+    }
+
+    async fn command(&mut self, spi: &mut SPI, command: Command) -> Result<(), SPI::Error> {
+        self.interface.cmd(spi, command).await?;
+        if let Some(parameter) = command.parameter() {
+            self.interface.cmd(spi, parameter).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::graphics::TwoBitColorDisplay;
+    use crate::st7571::prelude::Display2in3;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use core::future::Future;
+    use core::task::{Context, Poll, Waker};
+    use embedded_graphics::geometry::{Point, Size};
+    use embedded_hal_async::spi::{ErrorType, Operation};
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    /// Polls `future` until it's done, the mocks never wait
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    /// Records every byte with the level of DC, true for data
+    struct Spi {
+        dc: Rc<Cell<bool>>,
+        bytes: Vec<(bool, u8)>,
+    }
+
+    impl Spi {
+        /// Sent commands, parameters included
+        fn commands(&self) -> Vec<u8> {
+            self.bytes
+                .iter()
+                .filter(|(data, _)| !data)
+                .map(|(_, byte)| *byte)
+                .collect()
+        }
+
+        /// Sent data bytes
+        fn data(&self) -> Vec<u8> {
+            self.bytes
+                .iter()
+                .filter(|(data, _)| *data)
+                .map(|(_, byte)| *byte)
+                .collect()
+        }
+    }
+
+    impl ErrorType for Spi {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice for Spi {
+        async fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u8>],
+        ) -> Result<(), Infallible> {
+            let data = self.dc.get();
+            for operation in operations {
+                if let Operation::Write(words) = operation {
+                    self.bytes.extend(words.iter().map(|byte| (data, *byte)));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct Pin(Option<Rc<Cell<bool>>>);
+
+    impl embedded_hal_v2::digital::ErrorType for Pin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Pin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            if let Some(level) = &self.0 {
+                level.set(false);
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            if let Some(level) = &self.0 {
+                level.set(true);
+            }
+            Ok(())
+        }
+    }
+
+    struct Delay;
+
+    impl DelayNs for Delay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    type Lcd = Lcd2in3<Spi, Pin, Pin, Delay>;
+
+    /// Driver after init, the bytes sent by init are kept
+    fn lcd(config: St7571Config) -> (Spi, Lcd) {
+        let dc = Rc::new(Cell::new(false));
+        let mut spi = Spi {
+            dc: dc.clone(),
+            bytes: Vec::new(),
+        };
+        let lcd = block_on(Lcd2in3::with_config(
+            &mut spi,
+            Pin(Some(dc)),
+            Pin(None),
+            &mut Delay,
+            config,
+        ))
+        .unwrap();
+        (spi, lcd)
+    }
+
+    #[test]
+    fn init_matches_blocking_driver() {
+        let (spi, _lcd) = lcd(St7571Config::default());
+        assert!(spi.data().is_empty());
+        assert_eq!(
+            spi.commands(),
+            [
+                0xE2, 0xAE, 0x38, 0xF4, 0xA0, 0xC8, 0x44, 0x00, 0x40, 0x00, 0xAB, 0x27, 0x81, 40,
+                0x57, 0x48, 0x61, 0x2C, 0x2E, 0x2F, 0x7B, 0x10, 0x00, 0xA6, 0xA4, 0xAF,
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_and_region_are_sent_page_by_page() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        let mut display = Display2in3::default();
+        for (index, byte) in display.get_mut_buffer().iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        spi.bytes.clear();
+        block_on(lcd.update_frame(&mut spi, display.buffer(), &mut Delay)).unwrap();
+        assert_eq!(spi.commands().len(), 12 * 3);
+        assert_eq!(spi.commands()[..6], [0xB0, 0x10, 0x00, 0xB1, 0x10, 0x00]);
+        assert_eq!(spi.data(), display.buffer());
+
+        spi.bytes.clear();
+        let area = Rectangle::new(Point::new(20, 10), Size::new(3, 4));
+        block_on(lcd.update_region(&mut spi, display.buffer(), area)).unwrap();
+        assert_eq!(spi.commands(), [0xB1, 0x11, 0x04]);
+        assert_eq!(spi.data(), display.buffer()[256 + 40..256 + 46]);
+    }
+
+    #[test]
+    fn power_save_restores_switches() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        block_on(async {
+            lcd.set_inverted(&mut spi, true).await?;
+            lcd.set_display_on(&mut spi, false).await?;
+            lcd.set_power_save(&mut spi, true).await?;
+            lcd.set_power_save(&mut spi, false).await
+        })
+        .unwrap();
+        assert_eq!(
+            spi.commands(),
+            [0xA7, 0xAE, 0xAE, 0xA5, 0xA9, 0xE1, 0xA4, 0xAE]
+        );
+        assert!(lcd.inverted() && !lcd.display_on() && !lcd.power_save());
+    }
+
+    #[test]
+    fn partial_display_keeps_scroll_position() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        block_on(lcd.enter_partial_display(&mut spi, 88, 16)).unwrap();
+        assert_eq!(lcd.partial_display(), Some((88, 8)));
+        assert_eq!(spi.commands(), [0x48, 17, 0x50, 0x44, 88, 0x40, 88]);
+
+        spi.bytes.clear();
+        block_on(lcd.set_start_line(&mut spi, 50)).unwrap();
+        block_on(lcd.exit_partial_display(&mut spi)).unwrap();
+        assert_eq!(
+            spi.commands(),
+            [0x40, 10, 0x48, 97, 0x57, 0x44, 0, 0x40, 50]
+        );
+    }
+}
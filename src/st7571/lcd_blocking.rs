@@ -0,0 +1,821 @@
+use embedded_graphics::primitives::Rectangle;
+use embedded_hal::{
+    blocking::{delay::*, spi::Write},
+    digital::v2::*,
+};
+
+use crate::interface::DisplayInterface;
+use crate::traits::{InternalWiAdditions, WaveshareDisplay};
+use crate::color::TwoBitColor;
+use crate::st7571::command::Command;
+#[cfg(doc)]
+use crate::st7571::prelude::Display2in3;
+use crate::st7571::{
+    fill_pattern, icon_pattern, init_sequence, partial_sequence, scrolled_line, Mode, St7571Config, State, Window,
+    DEFAULT_BACKGROUND_COLOR, POWER_OFF_MS, POWER_RAMP,
+};
+
+/// Lcd2in3 driver
+///
+pub struct Lcd2in3<SPI, CS, DC, RST, DELAY> {
+    /// Connection Interface
+    interface: DisplayInterface<SPI, CS, DC, RST, DELAY>,
+    /// Background Color
+    color: TwoBitColor,
+    /// Settings sent during init
+    config: St7571Config,
+    /// Icon segments, bit n is glass column n of the icon line
+    icons: u128,
+    /// Switches changed after init
+    state: State,
+}
+
+impl<SPI, CS, DC, RST, DELAY> InternalWiAdditions<SPI, CS, DC, RST, DELAY>
+    for Lcd2in3<SPI, CS, DC, RST, DELAY>
+where
+    SPI: Write<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+    DELAY: DelayMs<u8>,
+{
+    fn init(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.interface.reset(delay, 10);
+        self.state = State::default();
+
+        for (command, wait) in init_sequence(&self.config) {
+            self.command(spi, command)?;
+            if wait > 0 {
+                delay.delay_ms(wait);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<SPI, CS, DC, RST, DELAY> WaveshareDisplay<SPI, CS, DC, RST, DELAY>
+    for Lcd2in3<SPI, CS, DC, RST, DELAY>
+where
+    SPI: Write<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+    DELAY: DelayMs<u8>,
+{
+    type DisplayColor = TwoBitColor;
+    fn new(spi: &mut SPI, cs: CS, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(cs, dc, rst);
+        let color = DEFAULT_BACKGROUND_COLOR;
+
+        let mut epd = Lcd2in3 { interface, color, config: St7571Config::default(), icons: 0, state: State::default() };
+
+        epd.init(spi, delay)?;
+
+        Ok(epd)
+    }
+
+    /// Sends a whole framebuffer of [St7571Config::buffer_len] bytes
+    ///
+    /// Panics if `buffer` has another length, e.g. a [Display2in3] for a glass which
+    /// isn't 128 columns wide.
+    fn update_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        _delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        assert_eq!(buffer.len(), self.config.buffer_len(), "framebuffer doesn't fit the config");
+        let window =
+            Window::new(0, 0, self.config.width, self.config.height, self.config.mode);
+        self.send_window(spi, window, buffer, self.config.width)
+    }
+
+    /// `buffer` holds the pages of the window, y and height are rounded out to whole pages
+    ///
+    /// The part of the window outside the glass is skipped.
+    fn update_partial_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), SPI::Error> {
+        match Window::partial(&self.config, buffer, x, y, width, height) {
+            Some(window) => self.send_window(spi, window, buffer, width),
+            None => Ok(()),
+        }
+    }
+
+    /// The RAM is shown right away
+    fn display_frame(&mut self, _spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
+        Ok(())
+    }
+
+    fn update_and_display_frame(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        delay: &mut DELAY,
+    ) -> Result<(), SPI::Error> {
+        self.update_frame(spi, buffer, delay)
+    }
+
+    fn clear_frame(&mut self, spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
+        let (pattern, bytes) = fill_pattern(self.color, self.config.mode);
+
+        //每页8行,每列一个或两个字节
+        for page in 0..self.config.height.div_ceil(8) {
+            self.goto(spi, 0, page as u8)?;
+            for _ in 0..self.config.width {
+                self.interface.data(spi, &pattern[..bytes])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_background_color(&mut self, color: TwoBitColor) {
+        self.color = color;
+    }
+
+    fn background_color(&self) -> &TwoBitColor {
+        &self.color
+    }
+
+    fn width(&self) -> u32 {
+        self.config.width
+    }
+
+    fn height(&self) -> u32 {
+        self.config.height
+    }
+}
+
+impl<SPI, CS, DC, RST, DELAY> Lcd2in3<SPI, CS, DC, RST, DELAY>
+where
+    SPI: Write<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+    DELAY: DelayMs<u8>,
+{
+
+
+    /// Creates the driver and initialises the lcd with `config`
+    ///
+    /// Panics if the glass is empty or it and its offsets don't fit into the RAM.
+    pub fn with_config(
+        spi: &mut SPI,
+        cs: CS,
+        dc: DC,
+        rst: RST,
+        delay: &mut DELAY,
+        config: St7571Config,
+    ) -> Result<Self, SPI::Error> {
+        let interface = DisplayInterface::new(cs, dc, rst);
+        let color = DEFAULT_BACKGROUND_COLOR;
+
+        let mut epd = Lcd2in3 { interface, color, config, icons: 0, state: State::default() };
+
+        epd.init(spi, delay)?;
+
+        Ok(epd)
+    }
+
+    /// Get the settings used by init
+    pub fn config(&self) -> &St7571Config {
+        &self.config
+    }
+
+    /// Changes the settings and initialises the lcd again
+    pub fn set_config(
+        &mut self,
+        spi: &mut SPI,
+        delay: &mut DELAY,
+        config: St7571Config,
+    ) -> Result<(), SPI::Error> {
+        self.config = config;
+        self.init(spi, delay)
+    }
+
+    /// Sets the electronic volume, 0-63, higher is darker
+    pub fn set_contrast(&mut self, spi: &mut SPI, contrast: u8) -> Result<(), SPI::Error> {
+        self.config.contrast = contrast;
+        self.command(spi, Command::ElectronicVolume(contrast))
+    }
+
+    /// Enters power save, the RAM is kept
+    pub fn sleep(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.set_power_save(spi, true)
+    }
+
+    /// Leaves power save and shows the RAM again
+    pub fn wake_up(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.set_power_save(spi, false)
+    }
+
+    /// Turns the display on or off, the RAM is kept
+    pub fn set_display_on(&mut self, spi: &mut SPI, on: bool) -> Result<(), SPI::Error> {
+        self.state.on = on;
+        self.command(spi, Command::DisplayOn(on))
+    }
+
+    /// Get whether the display is on
+    pub fn display_on(&self) -> bool {
+        self.state.on
+    }
+
+    /// Shows white as black and the grays swapped, without touching the RAM
+    pub fn set_inverted(&mut self, spi: &mut SPI, inverted: bool) -> Result<(), SPI::Error> {
+        self.state.inverted = inverted;
+        self.command(spi, Command::InverseDisplay(inverted))
+    }
+
+    /// Get whether the display is reversed
+    pub fn inverted(&self) -> bool {
+        self.state.inverted
+    }
+
+    /// Turns on all pixels regardless of the RAM, wins over [Self::set_inverted]
+    pub fn set_all_pixels_on(&mut self, spi: &mut SPI, on: bool) -> Result<(), SPI::Error> {
+        self.state.all_pixels_on = on;
+        self.command(spi, Command::AllPixelsOn(on))
+    }
+
+    /// Get whether all pixels are forced on
+    pub fn all_pixels_on(&self) -> bool {
+        self.state.all_pixels_on
+    }
+
+    /// Enters power save with the display off and all pixels on, or leaves it
+    ///
+    /// Leaving restores the display on and all pixels on switches set before.
+    pub fn set_power_save(&mut self, spi: &mut SPI, save: bool) -> Result<(), SPI::Error> {
+        let state = self.state;
+        if save {
+            self.command(spi, Command::DisplayOn(false))?;
+            self.command(spi, Command::AllPixelsOn(true))?;
+            self.command(spi, Command::PowerSave(true))?;
+        } else {
+            self.command(spi, Command::ReleasePowerSave)?;
+            self.command(spi, Command::AllPixelsOn(state.all_pixels_on))?;
+            self.command(spi, Command::DisplayOn(state.on))?;
+        }
+        self.state.power_save = save;
+        Ok(())
+    }
+
+    /// Get whether power save is entered
+    pub fn power_save(&self) -> bool {
+        self.state.power_save
+    }
+
+    /// Scans only `rows` rows from row `first`, the other rows stay blank
+    ///
+    /// Duty and bias are lowered to the band which cuts the power draw, at least 16 lines
+    /// are scanned. The content keeps its place on the glass.
+    pub fn enter_partial_display(
+        &mut self,
+        spi: &mut SPI,
+        first: u32,
+        rows: u32,
+    ) -> Result<(), SPI::Error> {
+        let first = first.min(self.config.height.saturating_sub(1));
+        let rows = rows.min(self.config.height - first).max(1);
+        self.state.partial = Some((first, rows));
+        self.send_partial(spi)
+    }
+
+    /// Scans the whole glass again with the duty and bias of the config
+    pub fn exit_partial_display(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.state.partial = None;
+        self.send_partial(spi)
+    }
+
+    /// First row and rows of the partial display, `None` if the whole glass is scanned
+    pub fn partial_display(&self) -> Option<(u32, u32)> {
+        self.state.partial
+    }
+
+    fn send_partial(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        for command in partial_sequence(&self.config, &self.state) {
+            self.command(spi, command)?;
+        }
+        Ok(())
+    }
+
+    /// Shuts the lcd down so VDD can be removed safely
+    ///
+    /// Turns the display off, then booster, regulator and follower, enters power save and
+    /// waits until the internal power is discharged. After VDD was removed the lcd needs a
+    /// new init, otherwise [Self::power_on] brings it back with the RAM kept.
+    pub fn power_off(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::DisplayOn(false))?;
+        let off = Command::PowerControl {
+            booster: false,
+            regulator: false,
+            follower: false,
+        };
+        self.command(spi, off)?;
+        self.command(spi, Command::PowerSave(true))?;
+        delay.delay_ms(POWER_OFF_MS);
+        self.state.on = false;
+        self.state.power_save = true;
+        Ok(())
+    }
+
+    /// Leaves power save, ramps up booster, regulator and follower and turns the display on
+    pub fn power_on(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
+        self.command(spi, Command::ReleasePowerSave)?;
+        for (command, wait) in POWER_RAMP {
+            self.command(spi, command)?;
+            delay.delay_ms(wait);
+        }
+        self.command(spi, Command::AllPixelsOn(self.state.all_pixels_on))?;
+        self.command(spi, Command::DisplayOn(true))?;
+        self.state.on = true;
+        self.state.power_save = false;
+        Ok(())
+    }
+
+    /// Switches between black and white and four gray levels
+    ///
+    /// The RAM is read in the new layout, send the frame of the matching display again.
+    pub fn set_mode(&mut self, spi: &mut SPI, mode: Mode) -> Result<(), SPI::Error> {
+        self.config.mode = mode;
+        self.command(spi, Command::Extension3)?;
+        self.command(spi, Command::GrayMode(mode == Mode::Gray4))?;
+        self.command(spi, Command::ExitExtension)
+    }
+
+    /// RAM line shown on the first row
+    pub fn start_line(&self) -> u8 {
+        self.state.start_line
+    }
+
+    /// Shows RAM line `line` on the first row, the RAM wraps after line 127
+    ///
+    /// Moves the content without sending pixel data, the lines below the glass can be
+    /// drawn in advance.
+    pub fn set_start_line(&mut self, spi: &mut SPI, line: u8) -> Result<(), SPI::Error> {
+        self.state.start_line = scrolled_line(line, 0);
+        let command = self.state.start_line_command();
+        self.command(spi, command)
+    }
+
+    /// Moves the content up by `lines`, one line every `step_ms`, negative values move it down
+    pub fn smooth_scroll(
+        &mut self,
+        spi: &mut SPI,
+        delay: &mut DELAY,
+        lines: i32,
+        step_ms: u8,
+    ) -> Result<(), SPI::Error> {
+        for _ in 0..lines.unsigned_abs() {
+            let line = scrolled_line(self.state.start_line, lines.signum());
+            self.set_start_line(spi, line)?;
+            delay.delay_ms(step_ms);
+        }
+        Ok(())
+    }
+
+    /// Icon segments, bit n is glass column n of the icon line
+    pub fn icons(&self) -> u128 {
+        self.icons
+    }
+
+    /// Turns one segment of the icon line on or off, the framebuffer is not touched
+    ///
+    /// `column` is a glass column like x, columns outside the glass are ignored.
+    pub fn set_icon(&mut self, spi: &mut SPI, column: u8, on: bool) -> Result<(), SPI::Error> {
+        let Some(ram_column) = self.config.ram_column(column) else {
+            return Ok(());
+        };
+        let bit = 1u128 << column;
+        let icons = if on { self.icons | bit } else { self.icons & !bit };
+        self.command(spi, Command::Icon(true))?;
+        self.command(spi, Command::Column(ram_column))?;
+        self.interface.data(spi, icon_pattern(on, self.config.mode))?;
+        self.finish_icons(spi, icons)
+    }
+
+    /// Writes the icon line, bit n is glass column n, bits outside the glass are ignored
+    pub fn set_icons(&mut self, spi: &mut SPI, icons: u128) -> Result<(), SPI::Error> {
+        let icons = icons & self.config.icon_mask();
+        self.command(spi, Command::Icon(true))?;
+        self.command(spi, Command::Column(self.config.column_offset))?;
+        for column in 0..self.config.width {
+            let on = icons >> column & 1 != 0;
+            self.interface.data(spi, icon_pattern(on, self.config.mode))?;
+        }
+        self.finish_icons(spi, icons)
+    }
+
+    /// Turns off all segments of the icon line
+    pub fn clear_icons(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        self.set_icons(spi, 0)
+    }
+
+    /// Keeps the icon line shown while a segment is on
+    fn finish_icons(&mut self, spi: &mut SPI, icons: u128) -> Result<(), SPI::Error> {
+        self.icons = icons;
+        if icons == 0 {
+            self.command(spi, Command::Icon(false))?;
+        }
+        Ok(())
+    }
+
+    /// Sends the part of a full framebuffer like [Display2in3] inside `area`
+    ///
+    /// `area` is in unrotated buffer coordinates, its rows are rounded out to whole pages
+    /// of 8 lines and it is clipped to the glass. Panics if `buffer` isn't
+    /// [St7571Config::buffer_len] bytes long.
+    pub fn update_region(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        area: Rectangle,
+    ) -> Result<(), SPI::Error> {
+        assert_eq!(buffer.len(), self.config.buffer_len(), "framebuffer doesn't fit the config");
+        match Window::clipped(&self.config, area) {
+            Some(window) => {
+                let stride = self.config.width;
+                self.send_window(spi, window, &buffer[window.offset(stride)..], stride)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the pages of `window`, `buffer` starts with its first column
+    fn send_window(
+        &mut self,
+        spi: &mut SPI,
+        window: Window,
+        buffer: &[u8],
+        stride: u32,
+    ) -> Result<(), SPI::Error> {
+        for (page, data) in window.pages(buffer, stride) {
+            self.goto(spi, window.x as u8, page)?;
+            self.interface.data(spi, data)?;
+        }
+        Ok(())
+    }
+
+    /// X对应玻璃上的列,值范围0到宽度-1,加上配置的列偏移,超出范围会panic
+    /// Y对应页,值范围0-15,每页8行
+    pub fn goto(&mut self, spi: &mut SPI, x: u8, y: u8) -> Result<(), SPI::Error> {
+        let column = self.config.ram_column(x).expect("column outside the glass");
+        self.command(spi, Command::Page(y))?;
+        self.command(spi, Command::Column(column))
+    }
+    ///
+    pub fn put_char(&mut self,spi: &mut SPI,data:&[u8]){
+        self.interface.data(spi, data);
+    }
+
+    fn send_data(&mut self, spi: &mut SPI, data: &[u8]) -> Result<(), SPI::Error> {
+        self.interface.data(spi, data)
+    }
+    fn command(&mut self, spi: &mut SPI, command: Command) -> Result<(), SPI::Error> {
+        self.interface.cmd(spi, command)?;
+        if let Some(parameter) = command.parameter() {
+            self.interface.cmd(spi, parameter)?;
+        }
+        Ok(())
+    }
+
+    fn send_resolution(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        Ok(())
+    }
+
+    /// Helper function. Sets up the display to send pixel data to a custom
+    /// starting point.
+    pub fn shift_display(
+        &mut self,
+        spi: &mut SPI,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), SPI::Error> {
+        self.send_data(spi, &[(x >> 8) as u8])?;
+        let tmp = x & 0xf8;
+        self.send_data(spi, &[tmp as u8])?; // x should be the multiple of 8, the last 3 bit will always be ignored
+        let tmp = tmp + width - 1;
+        self.send_data(spi, &[(tmp >> 8) as u8])?;
+        self.send_data(spi, &[(tmp | 0x07) as u8])?;
+
+        self.send_data(spi, &[(y >> 8) as u8])?;
+        self.send_data(spi, &[y as u8])?;
+
+        self.send_data(spi, &[((y + height - 1) >> 8) as u8])?;
+        self.send_data(spi, &[(y + height - 1) as u8])?;
+
+        self.send_data(spi, &[0x01])?; // Gates scan both inside and outside of the partial window. (default)
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::graphics::TwoBitColorDisplay;
+    use crate::st7571::prelude::{buffer_len, Display, Display2in3, Display2in3Mono, VarDisplay2in3};
+    use crate::st7571::{RAM_HEIGHT, RAM_WIDTH};
+    use embedded_graphics::geometry::{Point, Size};
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    /// Records every byte with the level of DC, true for data
+    pub(crate) struct Spi {
+        dc: Rc<Cell<bool>>,
+        pub(crate) bytes: Vec<(bool, u8)>,
+    }
+
+    impl Spi {
+        /// Sent commands, parameters included
+        pub(crate) fn commands(&self) -> Vec<u8> {
+            self.bytes.iter().filter(|(data, _)| !data).map(|(_, byte)| *byte).collect()
+        }
+
+        /// Sent data bytes
+        pub(crate) fn data(&self) -> Vec<u8> {
+            self.bytes.iter().filter(|(data, _)| *data).map(|(_, byte)| *byte).collect()
+        }
+    }
+
+    impl Write<u8> for Spi {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            let data = self.dc.get();
+            self.bytes.extend(words.iter().map(|byte| (data, *byte)));
+            Ok(())
+        }
+    }
+
+    pub(crate) struct Pin(Option<Rc<Cell<bool>>>);
+
+    impl OutputPin for Pin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            if let Some(level) = &self.0 {
+                level.set(false);
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            if let Some(level) = &self.0 {
+                level.set(true);
+            }
+            Ok(())
+        }
+    }
+
+    pub(crate) struct Delay;
+
+    impl DelayMs<u8> for Delay {
+        fn delay_ms(&mut self, _ms: u8) {}
+    }
+
+    pub(crate) type Lcd = Lcd2in3<Spi, Pin, Pin, Pin, Delay>;
+
+    /// Driver after init, the bytes sent by init are kept
+    pub(crate) fn lcd(config: St7571Config) -> (Spi, Lcd) {
+        let dc = Rc::new(Cell::new(false));
+        let mut spi = Spi { dc: dc.clone(), bytes: Vec::new() };
+        let lcd = Lcd2in3::with_config(&mut spi, Pin(None), Pin(Some(dc)), Pin(None), &mut Delay, config)
+            .unwrap();
+        (spi, lcd)
+    }
+
+    #[test]
+    fn config_changes_init() {
+        let (spi, _lcd) = lcd(St7571Config::default());
+        assert!(spi.data().is_empty());
+        assert_eq!(spi.commands().len(), 26);
+
+        let config = St7571Config { bias: 4, contrast: 0x30, frame_rate: 7, ..Default::default() };
+        let (spi, _lcd) = lcd(config);
+        let commands = spi.commands();
+        assert_eq!(commands[2..4], [0x38, 0x74]);
+        assert_eq!(commands[12..15], [0x81, 0x30, 0x54]);
+    }
+
+    #[test]
+    fn geometry_sets_size_and_duty() {
+        let config = St7571Config::default().with_geometry(RAM_WIDTH, RAM_HEIGHT);
+        let (mut spi, mut lcd) = lcd(config);
+        assert_eq!((lcd.width(), lcd.height()), (128, 128));
+        assert_eq!(spi.commands()[15..17], [0x48, 0x80]);
+
+        spi.bytes.clear();
+        lcd.clear_frame(&mut spi, &mut Delay).unwrap();
+        assert_eq!(spi.data().len(), 128 * 128 / 4);
+        assert_eq!(spi.commands()[spi.commands().len() - 3..], [0xBF, 0x10, 0x00]);
+
+        assert_eq!(St7571Config::default().with_geometry(128, 8).duty, 17);
+    }
+
+    #[test]
+    fn frame_is_sent_page_by_page() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        let mut display = Display2in3::default();
+        display.get_mut_buffer()[0] = 0x11;
+        display.get_mut_buffer()[256] = 0x22;
+        spi.bytes.clear();
+        lcd.update_frame(&mut spi, display.buffer(), &mut Delay).unwrap();
+        let commands = spi.commands();
+        assert_eq!(commands.len(), 12 * 3);
+        assert_eq!(commands[..6], [0xB0, 0x10, 0x00, 0xB1, 0x10, 0x00]);
+        assert_eq!(spi.data(), display.buffer());
+    }
+
+    #[test]
+    fn region_is_rounded_to_pages() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        let mut display = Display2in3::default();
+        let buffer = display.get_mut_buffer();
+        for (index, byte) in buffer.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        spi.bytes.clear();
+        let area = Rectangle::new(Point::new(20, 10), Size::new(3, 4));
+        lcd.update_region(&mut spi, display.buffer(), area).unwrap();
+        assert_eq!(spi.commands(), [0xB1, 0x11, 0x04]);
+        let start = 256 + 40;
+        assert_eq!(spi.data(), display.buffer()[start..start + 6]);
+
+        spi.bytes.clear();
+        lcd.update_partial_frame(&mut spi, &[1, 2, 3, 4], 5, 16, 2, 8).unwrap();
+        assert_eq!(spi.commands(), [0xB2, 0x10, 0x05]);
+        assert_eq!(spi.data(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn partial_frame_is_clipped() {
+        // pages 15 and 16 of a window at the bottom, only page 15 is on the 128 lines
+        let (mut spi, mut lcd) = lcd(St7571Config::default().with_geometry(128, 128));
+        spi.bytes.clear();
+        lcd.update_partial_frame(&mut spi, &[1, 2, 3, 4], 0, 120, 1, 16).unwrap();
+        assert_eq!(spi.commands(), [0xBF, 0x10, 0x00]);
+        assert_eq!(spi.data(), [1, 2]);
+    }
+
+    #[test]
+    fn sleep_keeps_ram() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.set_contrast(&mut spi, 50).unwrap();
+        lcd.sleep(&mut spi).unwrap();
+        lcd.wake_up(&mut spi).unwrap();
+        assert_eq!(spi.commands(), [0x81, 50, 0xAE, 0xA5, 0xA9, 0xE1, 0xA4, 0xAF]);
+        assert_eq!(lcd.config().contrast, 50);
+    }
+
+    #[test]
+    fn power_save_restores_switches() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.set_inverted(&mut spi, true).unwrap();
+        lcd.set_display_on(&mut spi, false).unwrap();
+        lcd.set_power_save(&mut spi, true).unwrap();
+        assert!(lcd.power_save());
+        lcd.set_power_save(&mut spi, false).unwrap();
+        assert_eq!(spi.commands(), [0xA7, 0xAE, 0xAE, 0xA5, 0xA9, 0xE1, 0xA4, 0xAE]);
+        assert!(lcd.inverted() && !lcd.display_on() && !lcd.all_pixels_on());
+    }
+
+    #[test]
+    fn mono_mode_halves_traffic() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.set_mode(&mut spi, Mode::Mono).unwrap();
+        assert_eq!(spi.commands(), [0x7B, 0x11, 0x00]);
+
+        let mut display = Display2in3Mono::default();
+        display.get_mut_buffer()[128] = 0x5A;
+        spi.bytes.clear();
+        lcd.update_frame(&mut spi, display.buffer(), &mut Delay).unwrap();
+        assert_eq!(spi.data(), display.buffer());
+        assert_eq!(spi.commands()[3..6], [0xB1, 0x10, 0x00]);
+
+        spi.bytes.clear();
+        lcd.set_background_color(TwoBitColor::Black);
+        lcd.clear_frame(&mut spi, &mut Delay).unwrap();
+        assert_eq!(spi.data().len(), 128 * 96 / 8);
+        assert!(spi.data().iter().all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn scroll_moves_start_line() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.set_start_line(&mut spi, 126).unwrap();
+        lcd.smooth_scroll(&mut spi, &mut Delay, 3, 20).unwrap();
+        assert_eq!(spi.commands(), [0x40, 126, 0x40, 127, 0x40, 0, 0x40, 1]);
+        assert_eq!(lcd.start_line(), 1);
+
+        spi.bytes.clear();
+        lcd.smooth_scroll(&mut spi, &mut Delay, -2, 20).unwrap();
+        assert_eq!(spi.commands(), [0x40, 0, 0x40, 127]);
+        assert!(spi.data().is_empty());
+    }
+
+    #[test]
+    fn power_off_follows_datasheet() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.power_off(&mut spi, &mut Delay).unwrap();
+        assert_eq!(spi.commands(), [0xAE, 0x28, 0xA9]);
+        assert!(!lcd.display_on() && lcd.power_save());
+
+        spi.bytes.clear();
+        lcd.power_on(&mut spi, &mut Delay).unwrap();
+        assert_eq!(spi.commands(), [0xE1, 0x2C, 0x2E, 0x2F, 0xA4, 0xAF]);
+        assert!(lcd.display_on() && !lcd.power_save());
+    }
+
+    #[test]
+    fn partial_display_keeps_scroll_position() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.enter_partial_display(&mut spi, 88, 16).unwrap();
+        assert_eq!(lcd.partial_display(), Some((88, 8)));
+        assert_eq!(spi.commands(), [0x48, 17, 0x50, 0x44, 88, 0x40, 88]);
+
+        spi.bytes.clear();
+        lcd.set_start_line(&mut spi, 50).unwrap();
+        assert_eq!(spi.commands(), [0x40, 10]);
+
+        spi.bytes.clear();
+        lcd.exit_partial_display(&mut spi).unwrap();
+        assert_eq!(spi.commands(), [0x48, 97, 0x57, 0x44, 0, 0x40, 50]);
+    }
+
+    #[test]
+    fn column_offset_moves_uploads() {
+        let config = St7571Config::default().with_geometry(120, 96).with_offsets(4, 0);
+        let (mut spi, mut lcd) = lcd(config);
+        spi.bytes.clear();
+        let mut buffer = [0u8; buffer_len(120, 96)];
+        let display = VarDisplay2in3::new(120, 96, &mut buffer);
+        lcd.update_frame(&mut spi, display.buffer(), &mut Delay).unwrap();
+        assert_eq!(spi.commands()[..3], [0xB0, 0x10, 0x04]);
+        assert_eq!(spi.data().len(), 120 * 2 * 12);
+
+        spi.bytes.clear();
+        lcd.update_partial_frame(&mut spi, &[0; 4], 20, 8, 2, 8).unwrap();
+        assert_eq!(spi.commands(), [0xB1, 0x11, 0x08]);
+
+        spi.bytes.clear();
+        lcd.set_icon(&mut spi, 0x25, true).unwrap();
+        assert_eq!(spi.commands(), [0xA3, 0x12, 0x09]);
+        lcd.set_icon(&mut spi, 120, true).unwrap();
+        assert_eq!(lcd.icons(), 1 << 0x25);
+
+        spi.bytes.clear();
+        lcd.set_icons(&mut spi, u128::MAX).unwrap();
+        assert_eq!(spi.commands(), [0xA3, 0x10, 0x04]);
+        assert_eq!(spi.data().len(), 120 * 2);
+        assert_eq!(lcd.icons(), u128::MAX >> 8);
+    }
+
+    #[test]
+    #[should_panic]
+    fn fixed_framebuffer_needs_128_columns() {
+        let (mut spi, mut lcd) = lcd(St7571Config { width: 120, ..Default::default() });
+        let display = Display2in3::default();
+        let _ = lcd.update_frame(&mut spi, display.buffer(), &mut Delay);
+    }
+
+    #[test]
+    fn icons_bypass_framebuffer() {
+        let (mut spi, mut lcd) = lcd(St7571Config::default());
+        spi.bytes.clear();
+        lcd.set_icon(&mut spi, 0x25, true).unwrap();
+        assert_eq!(spi.commands(), [0xA3, 0x12, 0x05]);
+        assert_eq!(spi.data(), [0x01, 0x01]);
+        assert_eq!(lcd.icons(), 1 << 0x25);
+
+        spi.bytes.clear();
+        lcd.set_icon(&mut spi, 0x25, false).unwrap();
+        assert_eq!(spi.commands(), [0xA3, 0x12, 0x05, 0xA2]);
+        assert_eq!(spi.data(), [0x00, 0x00]);
+
+        spi.bytes.clear();
+        lcd.set_icons(&mut spi, 0b101).unwrap();
+        assert_eq!(spi.data().len(), 256);
+        assert_eq!(spi.data()[..6], [1, 1, 0, 0, 1, 1]);
+        assert_eq!(lcd.icons(), 0b101);
+    }
+}
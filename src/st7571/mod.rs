@@ -1,12 +1,14 @@
-use embedded_hal::{
-    blocking::{delay::*, spi::Write},
-    digital::v2::*,
+use embedded_graphics::{
+    geometry::{Point, Size},
+    primitives::Rectangle,
 };
 
-use crate::interface::DisplayInterface;
-use crate::traits::InternalWiAdditions;
-use crate::{color::TwoBitColor, prelude::*};
+use embedded_graphics_core::pixelcolor::BinaryColor;
+
+use crate::color::TwoBitColor;
+use crate::graphics::GrayEncoding;
 use command::Command;
+use prelude::Display2in3;
 
 //The Lookup Tables for the Display
 
@@ -14,280 +16,561 @@ use command::Command;
 pub const WIDTH: u32 = 128;
 /// Height of the display
 pub const HEIGHT: u32 = 96;
+/// Columns of the display RAM
+pub const RAM_WIDTH: u32 = 128;
+/// Lines of the display RAM, without the icon line
+pub const RAM_HEIGHT: u32 = 128;
 /// Default Background Color
 pub const DEFAULT_BACKGROUND_COLOR: TwoBitColor = TwoBitColor::White;
 
 mod command;
 mod graphics;
+#[cfg(feature = "async")]
+pub mod lcd_async;
+#[cfg(not(feature = "async"))]
+pub mod lcd_blocking;
 
 ///
 pub mod prelude {
-    pub use crate::st7571::graphics::Display2in3;
+    pub use crate::st7571::graphics::{
+        buffer_len, mono_buffer_len, Display2in3, Display2in3Mono, VarDisplay2in3, VarDisplay2in3Mono,
+    };
+    pub use crate::st7571::{Mode, St7571Config};
 
+    #[cfg(not(feature = "async"))]
     pub use crate::traits::{WaveshareDisplay, WaveshareThreeColorDisplay};
+    #[cfg(feature = "async")]
+    pub use crate::traits_async::WaveshareDisplay;
 
     pub use crate::color::TwoBitColor;
-    pub use crate::graphics::TwoBitColorDisplay;
+    pub use crate::graphics::{Display, GrayEncoding, TwoBitColorDisplay};
+}
+
+/// How the RAM is written and shown
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Mode {
+    /// Black and white, one byte per page column, see [Display2in3Mono](prelude::Display2in3Mono)
+    Mono,
+    /// Four gray levels, two bytes per column of a page, use [Display2in3](prelude::Display2in3)
+    #[default]
+    Gray4,
+}
+
+impl Mode {
+    /// Bytes sent for one column of a page
+    pub fn column_bytes(self) -> u32 {
+        match self {
+            Mode::Mono => 1,
+            Mode::Gray4 => 2,
+        }
+    }
 }
 
-/// Lcd2in3 driver
+/// Settings of the glass which are sent during init
 ///
-pub struct Lcd2in3<SPI, CS, DC, RST, DELAY> {
-    /// Connection Interface
-    interface: DisplayInterface<SPI, CS, DC, RST, DELAY>,
-    /// Background Color
-    color: TwoBitColor,
+/// The defaults fit the 2.3" 128x96 module, other glasses usually need another bias,
+/// duty and contrast.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct St7571Config {
+    /// Visible columns, up to [RAM_WIDTH]
+    pub width: u32,
+    /// Visible lines, up to [RAM_HEIGHT]
+    pub height: u32,
+    /// Gray or black and white RAM
+    pub mode: Mode,
+    /// Scans the SEG outputs from 127 to 0, for glasses mounted mirrored
+    pub seg_reverse: bool,
+    /// Scans the COM outputs from 127 to 0, for glasses mounted upside down
+    pub com_reverse: bool,
+    /// RAM column of the first visible column, `column_offset + width` up to [RAM_WIDTH]
+    pub column_offset: u8,
+    /// COM line of the first visible row, `row_offset + height` up to [RAM_HEIGHT]
+    pub row_offset: u8,
+    /// LCD bias, 0 (1/5) to 7 (1/12)
+    pub bias: u8,
+    /// Duty register, 1/(duty+1) including the icon line, 16-128
+    pub duty: u8,
+    /// Electronic volume, 0-63
+    pub contrast: u8,
+    /// Internal regulator resistor ratio, 0-7
+    pub regulator_ratio: u8,
+    /// Booster efficiency, 0-3
+    pub booster_level: u8,
+    /// Frame rate step, 0-15
+    pub frame_rate: u8,
 }
 
-impl<SPI, CS, DC, RST, DELAY> InternalWiAdditions<SPI, CS, DC, RST, DELAY>
-    for Lcd2in3<SPI, CS, DC, RST, DELAY>
-where
-    SPI: Write<u8>,
-    CS: OutputPin,
-    DC: OutputPin,
-    RST: OutputPin,
-    DELAY: DelayMs<u8>,
-{
-    fn init(&mut self, spi: &mut SPI, delay: &mut DELAY) -> Result<(), SPI::Error> {
-        self.interface.reset(delay, 10);
+impl Default for St7571Config {
+    fn default() -> Self {
+        St7571Config {
+            width: WIDTH,
+            height: HEIGHT,
+            mode: Mode::Gray4,
+            seg_reverse: false,
+            com_reverse: true,
+            column_offset: 0,
+            row_offset: 0,
+            bias: 7,
+            duty: HEIGHT as u8 + 1,
+            contrast: 40,
+            regulator_ratio: 7,
+            booster_level: 1,
+            frame_rate: 15,
+        }
+    }
+}
 
-        self.command_u8(spi, 0xE2);
+impl St7571Config {
+    /// Sets the size of the glass and the matching duty, 128x128 scans the whole RAM
+    pub fn with_geometry(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self.check();
+        // 1/129 is the largest duty, duties below 16 are ignored by the controller
+        self.duty = (height.max(PARTIAL_MIN_LINES) + 1).min(RAM_HEIGHT) as u8;
+        self
+    }
 
-        delay.delay_ms(100);
+    /// Sets the RAM column and the COM line the glass starts at
+    pub fn with_offsets(mut self, column_offset: u8, row_offset: u8) -> Self {
+        self.column_offset = column_offset;
+        self.row_offset = row_offset;
+        self.check();
+        self
+    }
 
-        self.command_u8(spi, 0xAE);
-        self.command_u8(spi, 0x38);
-        self.command_u8(spi, 0xF4);
-        self.command_u8(spi, 0xA0);
-        self.command_u8(spi, 0xC8);
+    /// RAM column of glass column `x`, None outside the glass
+    fn ram_column(&self, x: u8) -> Option<u8> {
+        (u32::from(x) < self.width)
+            .then_some(x)
+            .and_then(|x| x.checked_add(self.column_offset))
+    }
 
-        self.command_u8(spi, 0x44);
-        self.command_u8(spi, 0x00);
+    /// Icon bits of the glass columns
+    fn icon_mask(&self) -> u128 {
+        u128::MAX.checked_shr(RAM_WIDTH - self.width).unwrap_or(0)
+    }
 
-        self.command_u8(spi, 0x40);
-        self.command_u8(spi, 0x00);
-    
-        self.command_u8(spi, 0xAB);
-        self.command_u8(spi, 0x27);
+    /// Panics if the glass is empty or it and its offsets don't fit into the RAM
+    fn check(&self) {
+        assert!(self.width > 0 && self.height > 0, "empty glass");
+        assert!(
+            self.width + u32::from(self.column_offset) <= RAM_WIDTH
+                && self.height + u32::from(self.row_offset) <= RAM_HEIGHT,
+            "glass doesn't fit into the RAM"
+        );
+    }
 
-        self.command_u8(spi, 0x81);
-        self.command_u8(spi, 40);
+    /// Bytes of a framebuffer for the glass in the configured mode
+    ///
+    /// [Display2in3] and [Display2in3Mono](prelude::Display2in3Mono) only fit the 128x96
+    /// module, other glasses need a [VarDisplay2in3](prelude::VarDisplay2in3) or
+    /// [VarDisplay2in3Mono](prelude::VarDisplay2in3Mono) of this size.
+    pub fn buffer_len(&self) -> usize {
+        match self.mode {
+            Mode::Mono => graphics::mono_buffer_len(self.width, self.height),
+            Mode::Gray4 => graphics::buffer_len(self.width, self.height),
+        }
+    }
+}
 
+/// Turns on booster, regulator and follower one after the other, with the ms to wait after each
+const POWER_RAMP: [(Command, u8); 3] = [
+    (
+        Command::PowerControl {
+            booster: true,
+            regulator: false,
+            follower: false,
+        },
+        100,
+    ),
+    (
+        Command::PowerControl {
+            booster: true,
+            regulator: true,
+            follower: false,
+        },
+        100,
+    ),
+    (
+        Command::PowerControl {
+            booster: true,
+            regulator: true,
+            follower: true,
+        },
+        10,
+    ),
+];
+
+/// Time the internal power needs to discharge after power save, tPOFF
+const POWER_OFF_MS: u8 = 250;
+
+/// Commands sent by init after the hardware reset, each with the ms to wait after it
+///
+/// Panics if the glass is empty or it and its offsets don't fit into the RAM.
+fn init_sequence(config: &St7571Config) -> [(Command, u8); 21] {
+    config.check();
+    [
+        (Command::Reset, 100),
+        (Command::DisplayOn(false), 0),
+        (
+            Command::ModeSet {
+                frame_rate: config.frame_rate,
+                booster: config.booster_level,
+            },
+            0,
+        ),
+        (Command::SegReverse(config.seg_reverse), 0),
+        (Command::ComReverse(config.com_reverse), 0),
+        (Command::Com0(config.row_offset), 0),
+        (State::default().start_line_command(), 0),
+        (Command::OscillatorOn, 0),
+        (Command::RegulatorRatio(config.regulator_ratio), 0),
+        (Command::ElectronicVolume(config.contrast), 0),
+        (Command::Bias(config.bias), 0),
+        (Command::Duty(config.duty), 0),
+        // turn on booster, regulator and follower one after the other
+        POWER_RAMP[0],
+        POWER_RAMP[1],
+        POWER_RAMP[2],
+        (Command::Extension3, 0),
+        (Command::GrayMode(config.mode == Mode::Gray4), 0),
+        (Command::ExitExtension, 0),
+        (Command::InverseDisplay(false), 0),
+        (Command::AllPixelsOn(false), 0),
+        (Command::DisplayOn(true), 10),
+    ]
+}
 
-        self.command_u8(spi, 0x57);
-        self.command_u8(spi, 0x48);
-        self.command_u8(spi, 0x61);
+/// Data of one icon column, only bit 0 of the icon page is shown and both gray bytes get it
+fn icon_pattern(on: bool, mode: Mode) -> &'static [u8] {
+    let pattern: &[u8] = if on { &[1, 1] } else { &[0, 0] };
+    &pattern[..mode.column_bytes() as usize]
+}
 
+/// Data of one column of a page filled with `color`
+fn fill_pattern(color: TwoBitColor, mode: Mode) -> ([u8; 2], usize) {
+    match mode {
+        Mode::Mono => match BinaryColor::from(color) {
+            BinaryColor::On => ([0xFF, 0], 1),
+            BinaryColor::Off => ([0x00, 0], 1),
+        },
+        Mode::Gray4 => (Display2in3::fill_pattern(color), 2),
+    }
+}
 
-        self.command_u8(spi, 0x2C);
-        delay.delay_ms(100);
+/// Switches of the driver which are not part of the config, init resets them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct State {
+    /// RAM line shown on the first row
+    start_line: u8,
+    /// Display on
+    on: bool,
+    /// Reverse display
+    inverted: bool,
+    /// Entire display on
+    all_pixels_on: bool,
+    /// Power save entered
+    power_save: bool,
+    /// First row and rows of the partial display
+    partial: Option<(u32, u32)>,
+}
 
-        self.command_u8(spi, 0x2E);
-        delay.delay_ms(100);
+impl Default for State {
+    fn default() -> Self {
+        State {
+            start_line: 0,
+            on: true,
+            inverted: false,
+            all_pixels_on: false,
+            power_save: false,
+            partial: None,
+        }
+    }
+}
 
-        self.command_u8(spi, 0x2F);
-        delay.delay_ms(10);
+/// Start line after moving the content up by `lines`, wraps around the RAM
+fn scrolled_line(start: u8, lines: i32) -> u8 {
+    (i32::from(start) + lines).rem_euclid(RAM_HEIGHT as i32) as u8
+}
 
-        self.command_u8(spi, 0x7B);
-        self.command_u8(spi, 0x10);
-        self.command_u8(spi, 0x00);
+impl State {
+    /// Start line command for the current scroll position
+    ///
+    /// The scan starts at COM0, row 0 of the glass on COM `row_offset` shows RAM line
+    /// `start_line`. A partial display starts the scan at its first row instead.
+    fn start_line_command(&self) -> Command {
+        let shift = self.partial.map_or(0, |(first, _)| first as i32);
+        Command::StartLine(scrolled_line(self.start_line, shift))
+    }
+}
 
+/// Fewest lines the controller scans, in full and partial display
+const PARTIAL_MIN_LINES: u32 = 16;
 
-        self.command_u8(spi, 0xa6);
-        self.command_u8(spi, 0xa4);
-        self.command_u8(spi, 0xaF);
+/// Bias register for a duty, the best bias is about 1/(sqrt(duty)+1)
+fn bias_for_duty(duty: u32) -> u8 {
+    let root = (1..=12u32).min_by_key(|n| (n * n).abs_diff(duty)).unwrap_or(12);
+    ((root + 1).clamp(5, 12) - 5) as u8
+}
 
-        delay.delay_ms(10);
-        Ok(())
+/// Commands to scan only `rows` rows from `first`, or the whole glass for `None`
+fn partial_sequence(config: &St7571Config, state: &State) -> [Command; 4] {
+    match state.partial {
+        Some((first, rows)) => {
+            let duty = (rows.max(PARTIAL_MIN_LINES) + 1).min(RAM_HEIGHT);
+            [
+                Command::Duty(duty as u8),
+                Command::Bias(bias_for_duty(duty)),
+                Command::Com0((first + u32::from(config.row_offset)) as u8),
+                state.start_line_command(),
+            ]
+        }
+        None => [
+            Command::Duty(config.duty),
+            Command::Bias(config.bias),
+            Command::Com0(config.row_offset),
+            state.start_line_command(),
+        ],
     }
 }
 
-impl<SPI, CS, DC, RST, DELAY> WaveshareDisplay<SPI, CS, DC, RST, DELAY>
-    for Lcd2in3<SPI, CS, DC, RST, DELAY>
-where
-    SPI: Write<u8>,
-    CS: OutputPin,
-    DC: OutputPin,
-    RST: OutputPin,
-    DELAY: DelayMs<u8>,
-{
-    type DisplayColor = TwoBitColor;
-    fn new(spi: &mut SPI, cs: CS, dc: DC, rst: RST, delay: &mut DELAY) -> Result<Self, SPI::Error> {
-        let interface = DisplayInterface::new(cs, dc, rst);
-        let color = DEFAULT_BACKGROUND_COLOR;
-
-        let mut epd = Lcd2in3 { interface, color };
-
-        epd.init(spi, delay)?;
-
-        Ok(epd)
+/// Columns and pages written by a partial upload
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Window {
+    x: u32,
+    page: u32,
+    width: u32,
+    pages: u32,
+    bytes: u32,
+}
+
+impl Window {
+    /// Rows are rounded out to whole pages of 8 lines
+    fn new(x: u32, y: u32, width: u32, height: u32, mode: Mode) -> Self {
+        let page = y / 8;
+        Window {
+            x,
+            page,
+            width,
+            pages: (y + height).div_ceil(8) - page,
+            bytes: mode.column_bytes(),
+        }
     }
 
-    fn update_frame(
-        &mut self,
-        spi: &mut SPI,
-        buffer: &[u8],
-        _delay: &mut DELAY,
-    ) -> Result<(), SPI::Error> {
-        self.cmd_with_data_u8(spi, 0x04, &[0x00]);
-        self.cmd_with_data_u8(spi, 0x60, &[0x70]);
-        self.cmd_with_data_u8(spi, 0x01, buffer)?;
-        Ok(())
+    /// Part of `area` on the glass, `None` if nothing is left
+    fn clipped(config: &St7571Config, area: Rectangle) -> Option<Self> {
+        let glass = Rectangle::new(Point::zero(), Size::new(config.width, config.height));
+        let area = area.intersection(&glass);
+        if area.is_zero_sized() {
+            return None;
+        }
+        let Point { x, y } = area.top_left;
+        Some(Self::new(
+            x as u32,
+            y as u32,
+            area.size.width,
+            area.size.height,
+            config.mode,
+        ))
     }
 
-    fn update_partial_frame(
-        &mut self,
-        spi: &mut SPI,
+    /// Part of an upload of `width` x `height` at `x`, `y` on the glass, `buffer` holds the
+    /// pages of the whole upload
+    ///
+    /// Panics if `buffer` is shorter than these pages.
+    fn partial(
+        config: &St7571Config,
         buffer: &[u8],
         x: u32,
         y: u32,
         width: u32,
         height: u32,
-    ) -> Result<(), SPI::Error> {
-        Ok(())
+    ) -> Option<Self> {
+        let whole = Self::new(x, y, width, height, config.mode);
+        assert!(buffer.len() >= ((whole.pages * width * whole.bytes) as usize));
+        if x >= config.width || y >= config.height {
+            return None;
+        }
+        // only the right and bottom can be cut, so the window starts at the buffer start
+        let area = Rectangle::new(Point::new(x as i32, y as i32), Size::new(width, height));
+        Self::clipped(config, area)
     }
 
-    fn display_frame(&mut self, spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
-        Ok(())
+    /// Offset of the first column of the window in a buffer with pages of `stride` columns
+    fn offset(&self, stride: u32) -> usize {
+        ((self.page * stride + self.x) * self.bytes) as usize
     }
 
-    fn update_and_display_frame(
-        &mut self,
-        spi: &mut SPI,
-        buffer: &[u8],
-        delay: &mut DELAY,
-    ) -> Result<(), SPI::Error> {
-        Ok(())
+    /// RAM page and data of every page, `buffer` starts with the first column of the window
+    /// and holds pages of `stride` columns
+    fn pages<'a>(&self, buffer: &'a [u8], stride: u32) -> impl Iterator<Item = (u8, &'a [u8])> {
+        let (first, width) = (self.page, (self.width * self.bytes) as usize);
+        buffer
+            .chunks((stride * self.bytes) as usize)
+            .take(self.pages as usize)
+            .enumerate()
+            .map(move |(n, page)| ((first + n as u32) as u8, &page[..width]))
     }
+}
 
-    fn clear_frame(&mut self, spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
-        let color_value = self.color.get_byte_value();
-
-
-
-
-        let j = 0;
-        //y
-        self.command_u8(spi, (0b10110000 | (j & 0b00001111) as u8) );
-        //x
-
-        self.command_u8(spi, (0b00010000 | ((0 >> 4) & 0b00001111)) as u8);
-        self.command_u8(spi,  (0  & 0b00001111) as u8);
-
-        for j in 0..HEIGHT {
-            for i in 0..WIDTH/2 {
+#[cfg(test)]
+mod tests {
+    extern crate std;
 
-                self.interface.data(spi, &[0x00]);
+    use super::*;
+    use crate::Command as _;
 
-            }
+    #[test]
+    fn default_config_sends_module_init() {
+        let mut commands = std::vec::Vec::new();
+        for (command, _) in init_sequence(&St7571Config::default()) {
+            commands.push(command.address());
+            commands.extend(command.parameter());
         }
-
-    
-
-        Ok(())
-    }
-
-    fn set_background_color(&mut self, color: TwoBitColor) {
-        self.color = color;
+        assert_eq!(
+            commands,
+            [
+                0xE2, 0xAE, 0x38, 0xF4, 0xA0, 0xC8, 0x44, 0x00, 0x40, 0x00, 0xAB, 0x27, 0x81, 40,
+                0x57, 0x48, 0x61, 0x2C, 0x2E, 0x2F, 0x7B, 0x10, 0x00, 0xA6, 0xA4, 0xAF,
+            ]
+        );
     }
 
-    fn background_color(&self) -> &TwoBitColor {
-        &self.color
+    #[test]
+    fn mono_mode_changes_color_mode() {
+        let config = St7571Config {
+            mode: Mode::Mono,
+            ..Default::default()
+        };
+        let sequence = init_sequence(&config);
+        assert_eq!(sequence[16].0.address(), 0x11);
+        assert_eq!(fill_pattern(TwoBitColor::Gray2, Mode::Mono), ([0xFF, 0], 1));
+        assert_eq!(icon_pattern(true, Mode::Mono), [1]);
     }
 
-    fn width(&self) -> u32 {
-        WIDTH
+    #[test]
+    fn start_line_wraps() {
+        assert_eq!(scrolled_line(0, -1), 127);
+        assert_eq!(scrolled_line(120, 10), 2);
+        assert_eq!(scrolled_line(5, 3), 8);
     }
 
-    fn height(&self) -> u32 {
-        HEIGHT
+    #[test]
+    fn partial_display_reduces_duty_and_bias() {
+        let config = St7571Config::default();
+        let mut state = State { start_line: 120, ..Default::default() };
+        state.partial = Some((40, 8));
+        let commands = partial_sequence(&config, &state);
+        assert_eq!(commands[0], Command::Duty(17));
+        assert_eq!(commands[1], Command::Bias(0));
+        assert_eq!(commands[2], Command::Com0(40));
+        assert_eq!(commands[3], Command::StartLine(32));
+
+        state.partial = None;
+        let commands = partial_sequence(&config, &state);
+        assert_eq!(commands[..2], [Command::Duty(97), Command::Bias(7)]);
+        assert_eq!(commands[2..], [Command::Com0(0), Command::StartLine(120)]);
+        assert_eq!(bias_for_duty(97), 6);
+        assert_eq!(bias_for_duty(129), 7);
     }
-}
-
-impl<SPI, CS, DC, RST, DELAY> Lcd2in3<SPI, CS, DC, RST, DELAY>
-where
-    SPI: Write<u8>,
-    CS: OutputPin,
-    DC: OutputPin,
-    RST: OutputPin,
-    DELAY: DelayMs<u8>,
-{
-
-
-    /// X对应列,值范围0-239
-    /// Y对应页,值范围0-23,共24页,每页8行
-    pub fn goto(&mut self,spi:&mut SPI,X: u8, Y: u8) {
-     
-        //y
-        self.command_u8(spi, (0b10110000 | (Y & 0b00001111) as u8) );
-        //x
 
-        self.command_u8(spi, (0b00010000 | ((X >> 4) & 0b00001111)) as u8);
-        self.command_u8(spi,  (X  & 0b00001111) as u8);
-        
-
-    }
-    ///
-    pub fn put_char(&mut self,spi: &mut SPI,data:&[u8]){
-        self.interface.data(spi, data);
-    }
+    #[test]
+    fn orientation_and_offsets() {
+        // COM lines scanned for the glass rows, the last line of the duty is the icon line
+        fn scanned(commands: &[Command]) -> core::ops::Range<u32> {
+            let duty = commands.iter().find_map(|c| match c {
+                Command::Duty(duty) => Some(u32::from(*duty)),
+                _ => None,
+            });
+            let com0 = commands.iter().find_map(|c| match c {
+                Command::Com0(line) => Some(u32::from(*line)),
+                _ => None,
+            });
+            let com0 = com0.unwrap();
+            com0..com0 + duty.unwrap() - 1
+        }
 
-    fn send_data(&mut self, spi: &mut SPI, data: &[u8]) -> Result<(), SPI::Error> {
-        self.interface.data(spi, data)
-    }
-    fn command_u8(&mut self, spi: &mut SPI, command: u8) -> Result<(), SPI::Error> {
-        self.interface.cmd(spi, command)
-    }
-    fn cmd_with_data_u8(
-        &mut self,
-        spi: &mut SPI,
-        command: u8,
-        data: &[u8],
-    ) -> Result<(), SPI::Error> {
-        self.interface.cmd_with_data(spi, command, data)
+        let config = St7571Config {
+            seg_reverse: true,
+            com_reverse: false,
+            ..Default::default()
+        }
+        .with_offsets(0, 4);
+        let sequence = init_sequence(&config);
+        assert_eq!(sequence[3].0, Command::SegReverse(true));
+        assert_eq!(sequence[4].0, Command::ComReverse(false));
+        assert_eq!(sequence[6].0, Command::StartLine(0));
+        let commands: std::vec::Vec<_> = sequence.iter().map(|(command, _)| *command).collect();
+        assert_eq!(scanned(&commands), 4..100);
+
+        let state = State { partial: Some((10, 20)), ..Default::default() };
+        let commands = partial_sequence(&config, &state);
+        assert_eq!(commands[2..], [Command::Com0(14), Command::StartLine(10)]);
+        assert_eq!(scanned(&commands), 14..34);
+
+        let commands = partial_sequence(&config, &State::default());
+        assert_eq!(scanned(&commands), 4..100);
     }
 
-    fn command(&mut self, spi: &mut SPI, command: Command) -> Result<(), SPI::Error> {
-        self.interface.cmd(spi, command)
-    }
-    fn cmd_with_data(
-        &mut self,
-        spi: &mut SPI,
-        command: Command,
-        data: &[u8],
-    ) -> Result<(), SPI::Error> {
-        self.interface.cmd_with_data(spi, command, data)
+    #[test]
+    #[should_panic]
+    fn glass_is_not_empty() {
+        St7571Config::default().with_geometry(128, 0);
     }
 
-    fn send_resolution(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
-        Ok(())
+    #[test]
+    #[should_panic]
+    fn offsets_must_fit_the_ram() {
+        St7571Config::default().with_geometry(120, 64).with_offsets(9, 0);
     }
 
-    /// Helper function. Sets up the display to send pixel data to a custom
-    /// starting point.
-    pub fn shift_display(
-        &mut self,
-        spi: &mut SPI,
-        x: u32,
-        y: u32,
-        width: u32,
-        height: u32,
-    ) -> Result<(), SPI::Error> {
-        self.send_data(spi, &[(x >> 8) as u8])?;
-        let tmp = x & 0xf8;
-        self.send_data(spi, &[tmp as u8])?; // x should be the multiple of 8, the last 3 bit will always be ignored
-        let tmp = tmp + width - 1;
-        self.send_data(spi, &[(tmp >> 8) as u8])?;
-        self.send_data(spi, &[(tmp | 0x07) as u8])?;
-
-        self.send_data(spi, &[(y >> 8) as u8])?;
-        self.send_data(spi, &[y as u8])?;
-
-        self.send_data(spi, &[((y + height - 1) >> 8) as u8])?;
-        self.send_data(spi, &[(y + height - 1) as u8])?;
-
-        self.send_data(spi, &[0x01])?; // Gates scan both inside and outside of the partial window. (default)
-
-        Ok(())
+    #[test]
+    fn window_is_rounded_and_clipped() {
+        let window = Window::new(5, 10, 3, 8, Mode::Gray4);
+        assert_eq!(
+            window,
+            Window {
+                x: 5,
+                page: 1,
+                width: 3,
+                pages: 2,
+                bytes: 2
+            }
+        );
+
+        let config = St7571Config::default();
+        let buffer = [0; 20 * 2 * 3];
+        let window = Window::partial(&config, &buffer, 120, 90, 20, 20).unwrap();
+        assert_eq!((window.width, window.pages), (8, 1));
+        assert_eq!(Window::partial(&config, &buffer, 128, 0, 20, 20), None);
+
+        let area = Rectangle::new(Point::new(120, 90), Size::new(20, 20));
+        let window = Window::clipped(&config, area).unwrap();
+        assert_eq!(
+            window,
+            Window {
+                x: 120,
+                page: 11,
+                width: 8,
+                pages: 1,
+                bytes: 2
+            }
+        );
+        assert_eq!(window.offset(128), (11 * 128 + 120) * 2);
+        assert_eq!(
+            Window::clipped(&config, Rectangle::new(Point::new(0, 96), Size::new(8, 8))),
+            None
+        );
+
+        let buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let pages: std::vec::Vec<_> = Window::new(0, 8, 1, 16, Mode::Gray4)
+            .pages(&buffer, 2)
+            .collect();
+        assert_eq!(pages, [(1, &buffer[0..2]), (2, &buffer[4..6])]);
+        let pages: std::vec::Vec<_> = Window::new(1, 0, 2, 8, Mode::Mono)
+            .pages(&buffer[1..], 4)
+            .collect();
+        assert_eq!(pages, [(0, &buffer[1..3])]);
     }
 }
@@ -2,29 +2,86 @@ use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::geometry::Size;
 use embedded_graphics::Pixel;
 use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::geometry::Point;
+use embedded_graphics::image::GetPixel;
+use crate::snapshot::{BufferLayout, Snapshot};
 
-use crate::color::TwoBitColor;
-use crate::graphics::TwoBitColorDisplay;
+use crate::color::{GrayMap, TwoBitColor};
+use crate::graphics::{GrayEncoding, TwoBitColorDisplay};
 use crate::uc1638::{DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH};
-use crate::graphics::{Display, DisplayRotation};
+use crate::graphics::{Display, DisplayRotation, PageLayout, RasterOp};
+use embedded_graphics::primitives::Rectangle;
 
 
 /// 
 pub struct Display2in7 {
     buffer: [u8; WIDTH as usize * HEIGHT as usize / 4],
     rotation: DisplayRotation,
+    raster_op: RasterOp,
+    gray_map: GrayMap,
 }
 
 impl Default for Display2in7 {
     fn default() -> Self {
+        let mut buffer = [0; WIDTH as usize * HEIGHT as usize / 4];
+        Self::fill(&mut buffer, DEFAULT_BACKGROUND_COLOR);
         Display2in7 {
-            buffer: [DEFAULT_BACKGROUND_COLOR.get_byte_value();
-                WIDTH as usize * HEIGHT as usize / 4],
+            buffer,
             rotation: DisplayRotation::default(),
+            raster_op: RasterOp::default(),
+            gray_map: GrayMap::default(),
         }
     }
 }
 
+/// 4 pixels of 2 bits per byte: 00 white, 01 gray1, 10 gray2, 11 black
+impl GrayEncoding for Display2in7 {
+    const COLUMN_BYTES: usize = 1;
+
+    fn code(color: TwoBitColor) -> u8 {
+        match color {
+            TwoBitColor::White => 0b00,
+            TwoBitColor::Gray1 => 0b01,
+            TwoBitColor::Gray2 => 0b10,
+            TwoBitColor::Black => 0b11,
+        }
+    }
+
+    fn decode(code: u8) -> TwoBitColor {
+        match code & 0b11 {
+            0b00 => TwoBitColor::White,
+            0b01 => TwoBitColor::Gray1,
+            0b10 => TwoBitColor::Gray2,
+            _ => TwoBitColor::Black,
+        }
+    }
+
+    fn fill_pattern(color: TwoBitColor) -> [u8; 2] {
+        let byte = Self::code(color) * 0x55;
+        [byte, byte]
+    }
+}
+
+
+impl Display2in7 {
+    const LAYOUT: PageLayout = PageLayout {
+        width: WIDTH,
+        rows: 4,
+        bytes: 1,
+    };
+
+    /// Copies the pixels of `src` so that its top left corner ends up at `dst`
+    ///
+    /// Whole bytes are moved if the rows are aligned to the pages of 4 lines.
+    pub fn copy_region(&mut self, src: Rectangle, dst: Point) {
+        crate::graphics::copy_region(self, Self::LAYOUT, src, dst);
+    }
+
+    /// Moves the content of `area` by `dx`, `dy` and fills the uncovered part with `fill`
+    pub fn scroll_region(&mut self, area: Rectangle, dx: i32, dy: i32, fill: TwoBitColor) {
+        crate::graphics::scroll_region(self, Self::LAYOUT, area, dx, dy, fill);
+    }
+}
 
 impl DrawTarget for Display2in7 {
     type Color = TwoBitColor;
@@ -47,6 +104,41 @@ impl OriginDimensions for Display2in7 {
     }
 }
 
+impl Snapshot for Display2in7 {
+    const LAYOUT: BufferLayout = BufferLayout::InterleavedPages;
+    const BPP: u8 = 2;
+
+    fn page_data(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn page_data_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn snapshot_rotation(&self) -> DisplayRotation {
+        self.rotation
+    }
+
+    fn set_snapshot_rotation(&mut self, rotation: DisplayRotation) {
+        self.rotation = rotation;
+    }
+}
+
+impl GetPixel for Display2in7 {
+    type Color = TwoBitColor;
+
+    fn pixel(&self, point: Point) -> Option<TwoBitColor> {
+        if crate::graphics::outside_display(point, WIDTH, HEIGHT, self.rotation) {
+            return None;
+        }
+        let (index, bit) =
+            find_position(point.x as u32, point.y as u32, WIDTH, HEIGHT, self.rotation);
+        let code = (self.buffer[index as usize] & bit) >> bit.trailing_zeros();
+        Some(self.gray_map.unmap(Self::decode(code)))
+    }
+}
+
 
 
 
@@ -67,6 +159,21 @@ impl TwoBitColorDisplay for Display2in7 {
         self.rotation
     }
 
+    fn set_gray_map(&mut self, map: GrayMap) {
+        self.gray_map = map;
+    }
+
+    fn gray_map(&self) -> GrayMap {
+        self.gray_map
+    }
+
+    fn set_raster_op(&mut self, op: RasterOp) {
+        self.raster_op = op;
+    }
+
+    fn raster_op(&self) -> RasterOp {
+        self.raster_op
+    }
 
     fn draw_helper(
         &mut self,
@@ -75,9 +182,11 @@ impl TwoBitColorDisplay for Display2in7 {
         pixel: Pixel<TwoBitColor>,
     ) -> Result<(), Self::Error> {
         let rotation = self.rotation();
+        let op = self.raster_op();
+        let color = self.gray_map().map(pixel.1);
         let buffer = self.get_mut_buffer();
 
-        let Pixel(point, color) = pixel;
+        let Pixel(point, _) = pixel;
         if crate::graphics::outside_display(point, width, height, rotation) {
             return Ok(());
         }
@@ -89,26 +198,8 @@ impl TwoBitColorDisplay for Display2in7 {
         let index = index as usize;
 
         // "Draw" the Pixel on that bit
-        match color {
-            TwoBitColor::Black => {
-                // clear bit in bw-buffer -> black
-                buffer[index] |= bit;
-            }
-            TwoBitColor::White => {
-                // set bit in bw-buffer -> white
-                buffer[index] &= !bit;
-            },
-            TwoBitColor::Gray1 => {
-                let code:u8 =0x55  & bit;
-                buffer[index] &= !bit;//先置为零
-                buffer[index] |= code;
-            },
-            TwoBitColor::Gray2 =>{
-                let code:u8 =0xAA  & bit;
-                buffer[index] &= !bit;//先置为零
-                buffer[index] |= code;
-            }
-        }
+        let ink = Self::fill_pattern(color)[0];
+        buffer[index] = op.apply(buffer[index], ink, bit);
 
         Ok(())
     }
@@ -124,4 +215,64 @@ fn find_position(x: u32, y: u32, width: u32, height: u32, rotation: DisplayRotat
         width  * row + col ,
         0x03 << ((ny %4)*2)
     )
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::prelude::*;
+
+    #[test]
+    fn fill_produces_gray() {
+        let mut display = Display2in7::default();
+        for color in [TwoBitColor::Gray1, TwoBitColor::Gray2, TwoBitColor::Black] {
+            Display2in7::fill(display.get_mut_buffer(), color);
+            assert_eq!(display.pixel(Point::new(0, 0)), Some(color));
+            assert_eq!(display.pixel(Point::new(5, 13)), Some(color));
+        }
+    }
+
+    #[test]
+    fn xor_black_inverts_gray_code() {
+        let mut display = Display2in7::default();
+        let _ = Pixel(Point::new(3, 1), TwoBitColor::Gray1).draw(&mut display);
+        assert_eq!(display.buffer()[3], 0b0000_0100);
+
+        display.set_raster_op(RasterOp::Xor);
+        let _ = Pixel(Point::new(3, 1), TwoBitColor::Black).draw(&mut display);
+        assert_eq!(display.buffer()[3], 0b0000_1000);
+        let _ = Pixel(Point::new(3, 1), TwoBitColor::Black).draw(&mut display);
+        assert_eq!(display.buffer()[3], 0b0000_0100);
+    }
+
+    #[test]
+    fn gray_map_is_applied_and_reverted() {
+        let mut display = Display2in7::default();
+        display.set_gray_map(GrayMap::new(
+            TwoBitColor::Black,
+            TwoBitColor::Gray1,
+            TwoBitColor::Gray2,
+            TwoBitColor::White,
+        ));
+        let _ = Pixel(Point::new(3, 1), TwoBitColor::Gray1).draw(&mut display);
+        assert_eq!(display.buffer()[3], 0b0000_1000);
+        assert_eq!(display.pixel(Point::new(3, 1)), Some(TwoBitColor::Gray1));
+    }
+
+    #[test]
+    fn scroll_region_by_pages_and_pixels() {
+        let mut display = Display2in7::default();
+        let _ = Pixel(Point::new(5, 9), TwoBitColor::Gray2).draw(&mut display);
+
+        // page aligned, moves bytes
+        let screen = Rectangle::new(Point::zero(), Size::new(WIDTH, HEIGHT));
+        display.scroll_region(screen, 0, -4, TwoBitColor::Black);
+        assert_eq!(display.pixel(Point::new(5, 5)), Some(TwoBitColor::Gray2));
+        assert_eq!(display.pixel(Point::new(5, 9)), Some(TwoBitColor::White));
+        assert_eq!(display.pixel(Point::new(5, HEIGHT as i32 - 1)), Some(TwoBitColor::Black));
+
+        // not aligned, moves pixels
+        display.copy_region(Rectangle::new(Point::new(4, 4), Size::new(3, 3)), Point::new(5, 6));
+        assert_eq!(display.pixel(Point::new(6, 7)), Some(TwoBitColor::Gray2));
+        assert_eq!(display.pixel(Point::new(5, 5)), Some(TwoBitColor::Gray2));
+    }
+}
@@ -6,6 +6,7 @@ use crate::traits_async::{InternalWiAdditions, WaveshareDisplay};
 use crate::{color::TwoBitColor, prelude::*};
 use crate::uc1638::command::Command;
 use crate::uc1638::{DEFAULT_BACKGROUND_COLOR, HEIGHT, WIDTH};
+use crate::uc1638::prelude::{Display2in7, GrayEncoding};
 
 /// Lcd2in7 driver
 ///
@@ -117,7 +118,14 @@ for Lcd2in7<SPI,  DC, RST, DELAY>
     }
 
     async  fn clear_frame(&mut self, spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
-        let color_value = self.color.get_byte_value();
+        let color_value = Display2in7::fill_pattern(self.color)[0];
+
+        self.cmd_with_data_u8(spi, 0x04, &[0x00]).await?;
+        self.command_u8(spi, 0x60).await?;
+        self.command_u8(spi, 0x70).await?;
+
+        self.command_u8(spi, 0x01).await?;
+        self.interface.data_x_times(spi, color_value, WIDTH * HEIGHT / 4).await?;
 
         Ok(())
     }
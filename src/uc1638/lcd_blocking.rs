@@ -9,6 +9,7 @@ use crate::uc1638::DEFAULT_BACKGROUND_COLOR;
 use crate::uc1638::WIDTH;
 use crate::uc1638::HEIGHT;
 use crate::uc1638::Command;
+use crate::uc1638::prelude::{Display2in7, GrayEncoding};
 /// Lcd2in7 driver
 ///
 pub struct Lcd2in7<SPI, CS, DC, RST, DELAY> {
@@ -120,22 +121,14 @@ for Lcd2in7<SPI, CS, DC, RST, DELAY>
     }
 
     fn clear_frame(&mut self, spi: &mut SPI, _delay: &mut DELAY) -> Result<(), SPI::Error> {
-        let color_value = self.color.get_byte_value();
+        let color_value = Display2in7::fill_pattern(self.color)[0];
 
-        self.cmd_with_data_u8(spi, 0x04, &[0x00]);
-        self.command_u8(spi, 0x60);
-        self.command_u8(spi, 0x70);
-
-        for i in 0..WIDTH {
-            for j in 0..(HEIGHT / 2) {
-
-                self.cmd_with_data_u8(spi, 0x01, &[0x00]);
-
-            }
-        }
+        self.cmd_with_data_u8(spi, 0x04, &[0x00])?;
+        self.command_u8(spi, 0x60)?;
+        self.command_u8(spi, 0x70)?;
 
-        /*   self.goto(spi, 40, 40);
-          self.cmd_with_data_u8(spi, 0x01, &[0xFF,0x00,0xFF,0xFF]); */
+        self.command_u8(spi, 0x01)?;
+        self.interface.data_x_times(spi, color_value, WIDTH * HEIGHT / 4)?;
 
         Ok(())
     }
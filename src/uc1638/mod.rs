@@ -32,5 +32,5 @@ pub mod prelude {
     #[cfg(feature = "async")]
     pub use crate::traits_async::{WaveshareDisplay };
     pub use crate::color::TwoBitColor;
-    pub use crate::graphics::TwoBitColorDisplay;
+    pub use crate::graphics::{GrayEncoding, TwoBitColorDisplay};
 }